@@ -0,0 +1,11 @@
+-- Checkpoints of the last processed blocks, one row per indexed batch
+CREATE TABLE IF NOT EXISTS indexer_cursor (
+    name TEXT NOT NULL,
+    block_number BIGINT NOT NULL,
+    block_hash TEXT NOT NULL,
+    updated_at BIGINT NOT NULL,
+    PRIMARY KEY (name, block_number)
+);
+
+-- Block that produced the row, used to roll back orphaned rows on reorg
+ALTER TABLE bet_record ADD COLUMN IF NOT EXISTS block_number BIGINT;
@@ -0,0 +1,17 @@
+-- On-chain state of the bet that is not known when it is placed
+ALTER TABLE bet_record ADD COLUMN IF NOT EXISTS points BIGINT;
+ALTER TABLE bet_record ADD COLUMN IF NOT EXISTS reward NUMERIC(78, 0);
+ALTER TABLE bet_record ADD COLUMN IF NOT EXISTS win BOOLEAN;
+ALTER TABLE bet_record ADD COLUMN IF NOT EXISTS claimed BOOLEAN DEFAULT FALSE;
+
+-- Every gamble event applied to a bet, keyed by the log that produced it
+CREATE TABLE IF NOT EXISTS bet_event (
+    id SERIAL PRIMARY KEY,
+    bet_id BIGINT NOT NULL,
+    event_name TEXT NOT NULL,
+    tx_hash TEXT NOT NULL,
+    log_index BIGINT NOT NULL,
+    block_number BIGINT NOT NULL,
+    created_at BIGINT NOT NULL,
+    UNIQUE (tx_hash, log_index)
+);
@@ -0,0 +1,13 @@
+-- Exact token amounts and the log that produced each vault transaction
+ALTER TABLE vault_transaction ALTER COLUMN amount TYPE NUMERIC(78, 0) USING amount::NUMERIC(78, 0);
+ALTER TABLE vault_transaction ADD COLUMN IF NOT EXISTS shares NUMERIC(78, 0);
+ALTER TABLE vault_transaction ADD COLUMN IF NOT EXISTS nonce BIGINT;
+ALTER TABLE vault_transaction ADD COLUMN IF NOT EXISTS block_number BIGINT;
+ALTER TABLE vault_transaction ADD COLUMN IF NOT EXISTS log_index BIGINT;
+-- Guarded so databases migrated by hand before embedded migrations can adopt them
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = 'vault_transaction_log_key') THEN
+        ALTER TABLE vault_transaction ADD CONSTRAINT vault_transaction_log_key UNIQUE (transaction_id, log_index);
+    END IF;
+END $$;
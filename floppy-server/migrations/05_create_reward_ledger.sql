@@ -0,0 +1,20 @@
+-- Off-chain rewards credited to a wallet, withdrawable from the vault with a signed permit
+CREATE TABLE IF NOT EXISTS reward_ledger (
+    id SERIAL PRIMARY KEY,
+    wallet_id TEXT NOT NULL,
+    amount NUMERIC(78, 0) NOT NULL,
+    reason TEXT,
+    created_at BIGINT NOT NULL
+);
+
+-- Reward withdrawal permits issued by the server, reserved until their deadline
+CREATE TABLE IF NOT EXISTS vault_permit (
+    id SERIAL PRIMARY KEY,
+    requester TEXT NOT NULL,
+    recipient TEXT NOT NULL,
+    nonce BIGINT NOT NULL,
+    amount NUMERIC(78, 0) NOT NULL,
+    deadline BIGINT NOT NULL,
+    signature TEXT NOT NULL,
+    created_at BIGINT NOT NULL
+);
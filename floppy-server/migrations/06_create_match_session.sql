@@ -0,0 +1,3 @@
+-- Server-issued match sessions: the server picks the seed and start time, the client only submits the end
+ALTER TABLE match_record ADD COLUMN IF NOT EXISTS session_id TEXT UNIQUE;
+ALTER TABLE match_record ADD COLUMN IF NOT EXISTS seed BIGINT;
@@ -0,0 +1,12 @@
+-- Resolution permit issued for each bet, so a bet is only ever signed for one score
+CREATE TABLE IF NOT EXISTS gamble_permit (
+    bet_id BIGINT PRIMARY KEY,
+    match_id BIGINT NOT NULL,
+    requester TEXT NOT NULL,
+    receiver TEXT NOT NULL,
+    points BIGINT NOT NULL,
+    bet_amount NUMERIC(78, 0) NOT NULL,
+    deadline BIGINT NOT NULL,
+    signature TEXT NOT NULL,
+    created_at BIGINT NOT NULL
+);
@@ -0,0 +1,9 @@
+-- Bet amounts in wei instead of a lossy float of ether. Existing rows are converted as well as the
+-- float allows; running `floppy-server backfill` afterwards restores the exact on-chain amounts.
+-- Guarded so amounts already in wei are never scaled twice.
+DO $$
+BEGIN
+    IF (SELECT data_type FROM information_schema.columns WHERE table_name = 'bet_record' AND column_name = 'bet_amount') <> 'numeric' THEN
+        ALTER TABLE bet_record ALTER COLUMN bet_amount TYPE NUMERIC(78, 0) USING ROUND(bet_amount::NUMERIC * 1e18);
+    END IF;
+END $$;
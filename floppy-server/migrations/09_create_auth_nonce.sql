@@ -0,0 +1,5 @@
+-- Sign-In with Ethereum nonces, each usable for a single login
+CREATE TABLE IF NOT EXISTS auth_nonce (
+    nonce TEXT PRIMARY KEY,
+    expires_at BIGINT NOT NULL
+);
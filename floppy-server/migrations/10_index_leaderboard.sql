@@ -0,0 +1,4 @@
+-- Leaderboards rank the best ended match of each wallet within a period, optionally per bet tier
+CREATE INDEX IF NOT EXISTS match_record_leaderboard_idx ON match_record (status, end_time, player_point DESC);
+CREATE INDEX IF NOT EXISTS match_record_wallet_idx ON match_record (LOWER(wallet_id), player_point DESC, end_time);
+CREATE INDEX IF NOT EXISTS bet_record_match_id_idx ON bet_record (match_id, bet_tier);
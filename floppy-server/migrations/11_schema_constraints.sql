@@ -0,0 +1,35 @@
+-- match_record ids become BIGINT like the bet_record.match_id referencing them
+ALTER TABLE match_record ALTER COLUMN id TYPE BIGINT;
+ALTER SEQUENCE match_record_id_seq AS BIGINT;
+
+-- Unlinked bets have no match instead of the placeholder match 0
+ALTER TABLE bet_record ALTER COLUMN match_id DROP NOT NULL;
+UPDATE bet_record SET match_id = NULL
+WHERE match_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM match_record m WHERE m.id = bet_record.match_id);
+ALTER TABLE bet_record ADD CONSTRAINT bet_record_match_id_fkey
+    FOREIGN KEY (match_id) REFERENCES match_record (id) ON DELETE SET NULL;
+
+-- Permits follow their bet, which is deleted when a reorg orphans it
+DELETE FROM gamble_permit WHERE NOT EXISTS (SELECT 1 FROM bet_record b WHERE b.id = gamble_permit.bet_id);
+ALTER TABLE gamble_permit ADD CONSTRAINT gamble_permit_bet_id_fkey
+    FOREIGN KEY (bet_id) REFERENCES bet_record (id) ON DELETE CASCADE;
+ALTER TABLE gamble_permit ADD CONSTRAINT gamble_permit_match_id_fkey
+    FOREIGN KEY (match_id) REFERENCES match_record (id);
+
+-- match_id lookups use bet_record_match_id_idx from 10_index_leaderboard.sql
+CREATE INDEX IF NOT EXISTS bet_record_requester_idx ON bet_record (LOWER(requester_address));
+CREATE INDEX IF NOT EXISTS bet_record_status_idx ON bet_record (status);
+
+-- Enum columns only hold the Rust variant names
+UPDATE player SET status = 'Offline' WHERE status IS NULL OR status NOT IN ('Online', 'Offline');
+ALTER TABLE player ADD CONSTRAINT player_status_check CHECK (status IN ('Online', 'Offline'));
+
+UPDATE match_record SET status = 'OffMatch' WHERE status IS NULL OR status NOT IN ('OnMatch', 'OffMatch');
+ALTER TABLE match_record ADD CONSTRAINT match_record_status_check CHECK (status IN ('OnMatch', 'OffMatch'));
+
+UPDATE bet_record SET bet_tier = 'Unknown' WHERE bet_tier IS NULL OR bet_tier NOT IN ('Unknown', 'Bronze', 'Silver', 'Gold', 'Diamond');
+ALTER TABLE bet_record ADD CONSTRAINT bet_record_bet_tier_check
+    CHECK (bet_tier IN ('Unknown', 'Bronze', 'Silver', 'Gold', 'Diamond'));
+UPDATE bet_record SET status = 'Unknown' WHERE status IS NULL OR status NOT IN ('Unknown', 'Pending', 'Resolved', 'Canceled');
+ALTER TABLE bet_record ADD CONSTRAINT bet_record_status_check
+    CHECK (status IN ('Unknown', 'Pending', 'Resolved', 'Canceled'));
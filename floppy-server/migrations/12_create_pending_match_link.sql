@@ -0,0 +1,9 @@
+-- Match links requested for bets the indexer has not stored yet, completed when the bet is indexed.
+-- Keyed by wallet too, so only a link from the bet requester can be completed.
+CREATE TABLE IF NOT EXISTS pending_match_link (
+    bet_id BIGINT NOT NULL,
+    wallet_id TEXT NOT NULL,
+    match_id BIGINT NOT NULL REFERENCES match_record (id) ON DELETE CASCADE,
+    created_at BIGINT NOT NULL,
+    PRIMARY KEY (bet_id, wallet_id)
+);
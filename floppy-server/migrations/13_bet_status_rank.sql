@@ -0,0 +1,6 @@
+-- Order of bet statuses, so stale on-chain reads never move a bet back. Resolved and Canceled are
+-- both final.
+CREATE OR REPLACE FUNCTION bet_status_rank(status TEXT) RETURNS INT
+LANGUAGE SQL IMMUTABLE AS $$
+    SELECT CASE status WHEN 'Pending' THEN 1 WHEN 'Resolved' THEN 2 WHEN 'Canceled' THEN 2 ELSE 0 END
+$$;
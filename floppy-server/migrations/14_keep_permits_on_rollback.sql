@@ -0,0 +1,5 @@
+-- Reorg rollbacks reset orphaned bets instead of deleting them, and a bet holding an issued
+-- permit must never be deleted, or it could be signed again for another result
+ALTER TABLE gamble_permit DROP CONSTRAINT gamble_permit_bet_id_fkey;
+ALTER TABLE gamble_permit ADD CONSTRAINT gamble_permit_bet_id_fkey
+    FOREIGN KEY (bet_id) REFERENCES bet_record (id) ON DELETE RESTRICT;
@@ -0,0 +1,4 @@
+-- Reward withdrawals are attributed to the wallet that submitted the permit, the event only
+-- names the recipient. Rows indexed before hold the recipient as their wallet.
+ALTER TABLE vault_transaction ADD COLUMN IF NOT EXISTS recipient TEXT;
+UPDATE vault_transaction SET recipient = wallet_id WHERE transaction_type = 3 AND recipient IS NULL;
@@ -0,0 +1,3 @@
+-- Sessions whose single end submission failed verification are closed as rejected
+ALTER TABLE match_record DROP CONSTRAINT match_record_status_check;
+ALTER TABLE match_record ADD CONSTRAINT match_record_status_check CHECK (status IN ('OnMatch', 'OffMatch', 'Rejected'));
@@ -10,10 +10,14 @@ INSERT INTO vault_transaction (wallet_id, transaction_type, amount, transaction_
 
 -- Sample data for match_record table
 INSERT INTO match_record (wallet_id, start_time, end_time, play_data, player_point, status) VALUES
-('wallet1', 1633036800, 1633036900, 'data1', 10, 'OnMatch'),
-('wallet2', 1633037000, 1633037100, 'data2', 5, 'OffMatch');
+('wallet1', 1633036800, 1633036900, '{"version":1,"seed":7,"flaps":[],"frames":37}', 0, 'OnMatch'),
+('wallet2', 1633037000, 1633037100, '{"version":1,"seed":8,"flaps":[],"frames":37}', 0, 'OffMatch');
 
 -- Sample data for bet_record table
 INSERT INTO bet_record (id, match_id, requester_address, receiver_address, bet_tier, bet_amount, dead_line, timestamp, status) VALUES
-(1, 1, 'address1', 'address2', 'Gold', 10.0, 1633037200, 1633036800, 'Unknown'),
-(2, 2, 'address3', 'address4', 'Silver', 5.0, 1633037300, 1633037000, 'Unknown');
\ No newline at end of file
+(1, 1, 'address1', 'address2', 'Gold', 10000000000000000000, 1633037200, 1633036800, 'Unknown'),
+(2, 2, 'address3', 'address4', 'Silver', 5000000000000000000, 1633037300, 1633037000, 'Unknown');
+
+-- Sample data for reward_ledger table
+INSERT INTO reward_ledger (wallet_id, amount, reason, created_at) VALUES
+('wallet1', 1000000000000000000, 'season reward', 1633036800);
@@ -0,0 +1,223 @@
+//! Wallet authentication with Sign-In with Ethereum.
+//!
+//! A client fetches a nonce, signs an EIP-4361 message containing it with its wallet and trades
+//! the message and signature for a session token. Requests carrying the token as
+//! `Authorization: Bearer <token>` are authenticated by [`authenticate`], and handlers that need
+//! a wallet take an [`AuthenticatedWallet`] argument.
+
+pub mod siwe;
+
+use std::{
+    future::{ready, Ready},
+    str::FromStr,
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::header::AUTHORIZATION,
+    middleware::Next,
+    web, FromRequest, HttpMessage, HttpRequest,
+};
+use alloy::{primitives::Address, signers::Signature};
+use eyre::{bail, eyre, Result};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, error::Error, state::AppState};
+use siwe::SiweMessage;
+
+/// Tolerated clock drift of the wallet when checking `Issued At`, in seconds.
+const CLOCK_SKEW: i64 = 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    iat: i64,
+    exp: i64,
+}
+
+/// Verifies sign-in messages and issues and checks session tokens.
+pub struct Authenticator {
+    domain: String,
+    chain_id: u64,
+    session_ttl: i64,
+    nonce_ttl: i64,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+}
+
+impl Authenticator {
+    pub fn new(config: &Config) -> Result<Self> {
+        let secret = std::env::var(&config.auth.jwt_secret_env)
+            .map_err(|_| eyre!("{} must be set", config.auth.jwt_secret_env))?;
+        if secret.len() < 32 {
+            bail!(
+                "{} must be at least 32 bytes long",
+                config.auth.jwt_secret_env
+            );
+        }
+        Ok(Self::with_secret(config, secret.as_bytes()))
+    }
+
+    pub(crate) fn with_secret(config: &Config, secret: &[u8]) -> Self {
+        Self {
+            domain: config.auth.domain.clone(),
+            chain_id: config.chain_id,
+            session_ttl: config.auth.session_ttl,
+            nonce_ttl: config.auth.nonce_ttl,
+            encoding_key: EncodingKey::from_secret(secret),
+            decoding_key: DecodingKey::from_secret(secret),
+        }
+    }
+
+    pub fn nonce_ttl(&self) -> i64 {
+        self.nonce_ttl
+    }
+
+    /// Checks everything about a sign-in except its nonce, which the caller must consume.
+    pub fn verify_message(&self, message: &str, signature: &str) -> Result<SiweMessage> {
+        let parsed: SiweMessage = message.parse()?;
+        if parsed.domain != self.domain {
+            bail!("message is for domain {}", parsed.domain);
+        }
+        if parsed.chain_id != self.chain_id {
+            bail!("message is for chain {}", parsed.chain_id);
+        }
+        let now = chrono::Utc::now();
+        if parsed.issued_at.timestamp() > now.timestamp() + CLOCK_SKEW || !parsed.is_valid_at(now) {
+            bail!("message is expired or not yet valid");
+        }
+        let signer = Signature::from_str(signature)?.recover_address_from_msg(message)?;
+        if signer != parsed.address {
+            bail!("message was not signed by {}", parsed.address);
+        }
+        Ok(parsed)
+    }
+
+    /// Issues a session token for `wallet`. Returns the token and its expiry.
+    pub fn issue_token(&self, wallet: Address) -> Result<(String, i64)> {
+        let iat = chrono::Utc::now().timestamp();
+        let claims = Claims {
+            sub: wallet.to_string(),
+            iat,
+            exp: iat + self.session_ttl,
+        };
+        let token = encode(&Header::new(Algorithm::HS256), &claims, &self.encoding_key)?;
+        Ok((token, claims.exp))
+    }
+
+    pub fn verify_token(&self, token: &str) -> Result<Address> {
+        let claims = decode::<Claims>(
+            token,
+            &self.decoding_key,
+            &Validation::new(Algorithm::HS256),
+        )?
+        .claims;
+        Ok(Address::from_str(&claims.sub)?)
+    }
+}
+
+/// Wallet of the authenticated caller. Extracting it answers 401 to anonymous requests.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthenticatedWallet(pub Address);
+
+impl AuthenticatedWallet {
+    /// Whether `address` (as stored, in any case) is this wallet.
+    pub fn is(&self, address: &str) -> bool {
+        Address::from_str(address).is_ok_and(|address| address == self.0)
+    }
+}
+
+impl FromRequest for AuthenticatedWallet {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<AuthenticatedWallet>()
+                .copied()
+                .ok_or_else(|| Error::Unauthorized("authentication required".to_string()).into()),
+        )
+    }
+}
+
+/// Middleware resolving the bearer token, if any, to an [`AuthenticatedWallet`]. An invalid
+/// token is rejected rather than treated as anonymous.
+pub async fn authenticate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+    if let Some(token) = token {
+        let data = req
+            .app_data::<web::Data<AppState>>()
+            .ok_or_else(|| Error::Internal("authentication unavailable".to_string()))?;
+        let wallet = data
+            .auth
+            .verify_token(&token)
+            .map_err(|_| Error::Unauthorized("invalid or expired token".to_string()))?;
+        req.extensions_mut().insert(AuthenticatedWallet(wallet));
+    }
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::test_config;
+    use alloy::signers::{local::PrivateKeySigner, SignerSync};
+
+    fn authenticator() -> Authenticator {
+        let mut config = test_config();
+        config.auth.domain = "floppy.game".to_string();
+        Authenticator::with_secret(&config, b"0123456789abcdef0123456789abcdef")
+    }
+
+    fn sign_in_message(address: Address, chain_id: u64) -> String {
+        let now = chrono::Utc::now();
+        format!(
+            "floppy.game wants you to sign in with your Ethereum account:\n{}\n\nSign in to Floppy.\n\nURI: https://floppy.game\nVersion: 1\nChain ID: {}\nNonce: abcdef0123456789\nIssued At: {}\nExpiration Time: {}",
+            address.to_checksum(None),
+            chain_id,
+            now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            (now + chrono::Duration::minutes(10)).to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        )
+    }
+
+    #[test]
+    fn test_sign_in() {
+        let auth = authenticator();
+        let wallet = PrivateKeySigner::random();
+        let message = sign_in_message(wallet.address(), 2021);
+        let signature: [u8; 65] = wallet.sign_message_sync(message.as_bytes()).unwrap().into();
+        let signature = format!("0x{}", alloy::hex::encode(signature));
+
+        let parsed = auth.verify_message(&message, &signature).unwrap();
+        assert_eq!(parsed.address, wallet.address());
+
+        let (token, _) = auth.issue_token(parsed.address).unwrap();
+        assert_eq!(auth.verify_token(&token).unwrap(), wallet.address());
+        assert!(auth.verify_token(&format!("{}x", token)).is_err());
+
+        // Signed by another wallet
+        let other = PrivateKeySigner::random();
+        let forged: [u8; 65] = other.sign_message_sync(message.as_bytes()).unwrap().into();
+        assert!(auth
+            .verify_message(&message, &format!("0x{}", alloy::hex::encode(forged)))
+            .is_err());
+
+        // Signed for another chain
+        let message = sign_in_message(wallet.address(), 1);
+        let signature: [u8; 65] = wallet.sign_message_sync(message.as_bytes()).unwrap().into();
+        assert!(auth
+            .verify_message(&message, &format!("0x{}", alloy::hex::encode(signature)))
+            .is_err());
+    }
+}
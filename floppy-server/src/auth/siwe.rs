@@ -0,0 +1,202 @@
+//! Parsing of EIP-4361 (Sign-In with Ethereum) messages.
+
+use std::str::FromStr;
+
+use alloy::primitives::Address;
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+
+const PREAMBLE_SUFFIX: &str = " wants you to sign in with your Ethereum account:";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SiweError {
+    #[error("malformed message: {0}")]
+    Malformed(&'static str),
+    #[error("invalid {0}")]
+    InvalidField(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SiweMessage {
+    pub domain: String,
+    pub address: Address,
+    pub statement: Option<String>,
+    pub uri: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub nonce: String,
+    pub issued_at: DateTime<Utc>,
+    pub expiration_time: Option<DateTime<Utc>>,
+    pub not_before: Option<DateTime<Utc>>,
+    pub request_id: Option<String>,
+    pub resources: Vec<String>,
+}
+
+fn parse_time(value: &str, field: &'static str) -> Result<DateTime<Utc>, SiweError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| SiweError::InvalidField(field))
+}
+
+impl FromStr for SiweMessage {
+    type Err = SiweError;
+
+    fn from_str(message: &str) -> Result<Self, Self::Err> {
+        let mut lines = message.lines();
+        let domain = lines
+            .next()
+            .and_then(|line| line.strip_suffix(PREAMBLE_SUFFIX))
+            .filter(|domain| !domain.is_empty())
+            .ok_or(SiweError::Malformed("missing preamble"))?
+            .to_string();
+        // EIP-4361 requires the EIP-55 checksummed address
+        let address = lines
+            .next()
+            .and_then(|line| Address::parse_checksummed(line, None).ok())
+            .ok_or(SiweError::InvalidField("address"))?;
+        if lines.next() != Some("") {
+            return Err(SiweError::Malformed("missing blank line after address"));
+        }
+
+        let mut statement = None;
+        let mut uri = None;
+        let mut version = None;
+        let mut chain_id = None;
+        let mut nonce = None;
+        let mut issued_at = None;
+        let mut expiration_time = None;
+        let mut not_before = None;
+        let mut request_id = None;
+        let mut resources = Vec::new();
+        let mut in_resources = false;
+
+        for line in lines {
+            if in_resources {
+                let resource = line
+                    .strip_prefix("- ")
+                    .ok_or(SiweError::InvalidField("resources"))?;
+                resources.push(resource.to_string());
+                continue;
+            }
+            if line == "Resources:" {
+                in_resources = true;
+                continue;
+            }
+            match line.split_once(": ") {
+                Some(("URI", value)) => uri = Some(value.to_string()),
+                Some(("Version", value)) => version = Some(value.to_string()),
+                Some(("Chain ID", value)) => {
+                    chain_id = Some(
+                        value
+                            .parse()
+                            .map_err(|_| SiweError::InvalidField("chain id"))?,
+                    )
+                }
+                Some(("Nonce", value)) => {
+                    if value.len() < 8 || !value.chars().all(|c| c.is_ascii_alphanumeric()) {
+                        return Err(SiweError::InvalidField("nonce"));
+                    }
+                    nonce = Some(value.to_string())
+                }
+                Some(("Issued At", value)) => issued_at = Some(parse_time(value, "issued at")?),
+                Some(("Expiration Time", value)) => {
+                    expiration_time = Some(parse_time(value, "expiration time")?)
+                }
+                Some(("Not Before", value)) => not_before = Some(parse_time(value, "not before")?),
+                Some(("Request ID", value)) => request_id = Some(value.to_string()),
+                // The statement comes before the fields and is followed by a blank line
+                _ if uri.is_none() && !line.is_empty() && statement.is_none() => {
+                    statement = Some(line.to_string())
+                }
+                _ if line.is_empty() && uri.is_none() => (),
+                _ => return Err(SiweError::Malformed("unexpected line")),
+            }
+        }
+
+        let version = version.ok_or(SiweError::Malformed("missing version"))?;
+        if version != "1" {
+            return Err(SiweError::InvalidField("version"));
+        }
+        Ok(Self {
+            domain,
+            address,
+            statement,
+            uri: uri.ok_or(SiweError::Malformed("missing uri"))?,
+            version,
+            chain_id: chain_id.ok_or(SiweError::Malformed("missing chain id"))?,
+            nonce: nonce.ok_or(SiweError::Malformed("missing nonce"))?,
+            issued_at: issued_at.ok_or(SiweError::Malformed("missing issued at"))?,
+            expiration_time,
+            not_before,
+            request_id,
+            resources,
+        })
+    }
+}
+
+impl SiweMessage {
+    /// Whether the message is usable at `now`.
+    pub fn is_valid_at(&self, now: DateTime<Utc>) -> bool {
+        self.expiration_time.is_none_or(|time| now < time)
+            && self.not_before.is_none_or(|time| now >= time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::address;
+
+    const MESSAGE: &str = "floppy.game wants you to sign in with your Ethereum account:
+0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266
+
+Sign in to Floppy.
+
+URI: https://floppy.game/login
+Version: 1
+Chain ID: 2021
+Nonce: 32891756abcdef01
+Issued At: 2024-10-01T16:25:24Z
+Expiration Time: 2024-10-02T16:25:24Z
+Resources:
+- https://floppy.game/terms";
+
+    #[test]
+    fn test_parse_message() {
+        let message: SiweMessage = MESSAGE.parse().unwrap();
+
+        assert_eq!(message.domain, "floppy.game");
+        assert_eq!(
+            message.address,
+            address!("f39Fd6e51aad88F6F4ce6aB8827279cffFb92266")
+        );
+        assert_eq!(message.statement.as_deref(), Some("Sign in to Floppy."));
+        assert_eq!(message.chain_id, 2021);
+        assert_eq!(message.nonce, "32891756abcdef01");
+        assert_eq!(message.resources, vec!["https://floppy.game/terms"]);
+        assert!(message.is_valid_at(parse_time("2024-10-01T20:00:00Z", "").unwrap()));
+        assert!(!message.is_valid_at(parse_time("2024-10-03T00:00:00Z", "").unwrap()));
+    }
+
+    #[test]
+    fn test_parse_invalid_message() {
+        let lowercase = MESSAGE.replace(
+            "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+            "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266",
+        );
+        assert_eq!(
+            lowercase.parse::<SiweMessage>(),
+            Err(SiweError::InvalidField("address"))
+        );
+        assert_eq!(
+            MESSAGE
+                .replace("Version: 1", "Version: 2")
+                .parse::<SiweMessage>(),
+            Err(SiweError::InvalidField("version"))
+        );
+        assert!(MESSAGE
+            .replace("Nonce: 32891756abcdef01\n", "")
+            .parse::<SiweMessage>()
+            .is_err());
+    }
+}
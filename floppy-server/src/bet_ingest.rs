@@ -0,0 +1,43 @@
+//! Storage of on-chain bets, shared by the event listener and the bets syncer so both merge
+//! contract reads into the same rows the same way.
+
+use alloy::primitives::U256;
+use eyre::Result;
+use sqlx::PgPool;
+
+use crate::{db::bet_record, event_listener::IFloppyGamble, models::BetRecord};
+
+/// Merges `bet_info`, as read from the gamble contract, into bet `bet_id`. `block_number` is the
+/// block the bet was placed in, when known. Repeating it with the same or an older read changes
+/// nothing.
+pub async fn ingest_bet(
+    pool: &PgPool,
+    bet_id: U256,
+    bet_info: &IFloppyGamble::BetInfo,
+    block_number: Option<u64>,
+) -> Result<()> {
+    let bet_record = to_bet_record(bet_id, bet_info)?;
+    bet_record::upsert_bet_record(pool, &bet_record, block_number.map(|block| block as i64))
+        .await?;
+    Ok(())
+}
+
+/// The bet row matching `bet_info`, without its off-chain match link and deadline.
+pub fn to_bet_record(bet_id: U256, bet_info: &IFloppyGamble::BetInfo) -> Result<BetRecord> {
+    Ok(BetRecord {
+        id: bet_id.to_string().parse()?,
+        match_id: None,
+        requester_address: bet_info.requester.to_string(),
+        receiver_address: bet_info.receiver.to_string(),
+        bet_tier: Some(bet_info.tier.into()),
+        bet_amount: bet_info.amount.to_string(),
+        // Not part of the on-chain bet, the stored value is kept
+        dead_line: 0,
+        timestamp: bet_info.timestamp.to_string().parse()?,
+        status: Some(bet_info.status.into()),
+        points: Some(bet_info.points.to_string().parse()?),
+        reward: Some(bet_info.reward.to_string()),
+        win: Some(bet_info.win),
+        claimed: Some(bet_info.claimed),
+    })
+}
@@ -1,112 +1,288 @@
-use crate::{db::bet_record, models::BetRecord};
+//! Periodic reconciliation of stored bets with the gamble contract.
+//!
+//! The event listener keeps bets up to date, but a missed log, an RPC hiccup or a reorg can leave
+//! a row behind the chain. Every bet whose state can still change is re-read with
+//! `getBetInfoById`, a page at a time, and repaired when it drifted. `getBetsByStatus` is not used:
+//! it fills its result arrays by bet id instead of compacting them, so it reverts as soon as any
+//! bet has another status.
+
+use std::sync::RwLock;
+
 use alloy::{
-    primitives::{address, utils::format_units},
+    primitives::U256,
     providers::{ProviderBuilder, RootProvider},
-    sol,
     transports::http::{reqwest::Url, Client, Http},
 };
 use eyre::Result;
+use serde::Serialize;
 use sqlx::PgPool;
 use tokio::time::{interval, Duration};
-use FloppyGamble::FloppyGambleInstance;
 
-sol! {
-    #[allow(missing_docs)]
-    #[sol(rpc)]
-    FloppyGamble,
-    "abi/FloppyGamble.json"
+use crate::{
+    bet_ingest,
+    config::Config,
+    db::bet_record,
+    event_listener::{FloppyGamble, GambleContract},
+    models::{BetRecord, BetStatus},
+    supervisor::Worker,
+};
+
+/// Delay between two reconciliation runs.
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+/// Number of bets loaded from the database at once.
+const PAGE_SIZE: i64 = 100;
+
+/// Ways a stored bet can differ from the contract.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Drift {
+    /// The contract moved the bet to a later status.
+    StatusBehind,
+    /// The stored status is later than the contract's, e.g. after an orphaned event. Never
+    /// repaired, bet ingestion does not move a bet back.
+    StatusAhead,
+    /// Points, reward or outcome differ for the same status.
+    Outcome,
+    Claimed,
+    /// Requester, receiver, tier, amount or placement time differ.
+    Details,
 }
 
-sol! {
-    #[allow(missing_docs)]
-    #[sol(rpc)]
-    FloppyVault,
-    "abi/FloppyVault.json"
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DriftCounts {
+    pub status_behind: u64,
+    pub status_ahead: u64,
+    pub outcome: u64,
+    pub claimed: u64,
+    pub details: u64,
 }
 
-pub enum BetStatus {
-    Unknown,
-    Pending,
-    Resolved,
-    Canceled,
+impl DriftCounts {
+    fn record(&mut self, drift: Drift) {
+        match drift {
+            Drift::StatusBehind => self.status_behind += 1,
+            Drift::StatusAhead => self.status_ahead += 1,
+            Drift::Outcome => self.outcome += 1,
+            Drift::Claimed => self.claimed += 1,
+            Drift::Details => self.details += 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReconciliationCounts {
+    pub checked: u64,
+    /// Drifted bets written back from the contract.
+    pub repaired: u64,
+    /// Stored bets the contract does not know.
+    pub not_on_chain: u64,
+    /// Bets that could not be read from the contract.
+    pub errors: u64,
+    pub drift: DriftCounts,
+}
+
+impl ReconciliationCounts {
+    fn add(&mut self, other: &ReconciliationCounts) {
+        self.checked += other.checked;
+        self.repaired += other.repaired;
+        self.not_on_chain += other.not_on_chain;
+        self.errors += other.errors;
+        self.drift.status_behind += other.drift.status_behind;
+        self.drift.status_ahead += other.drift.status_ahead;
+        self.drift.outcome += other.drift.outcome;
+        self.drift.claimed += other.drift.claimed;
+        self.drift.details += other.drift.details;
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReconciliationRun {
+    pub started_at: i64,
+    pub finished_at: i64,
+    #[serde(flatten)]
+    pub counts: ReconciliationCounts,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BetsSyncReport {
+    pub runs: u64,
+    pub last_run: Option<ReconciliationRun>,
+    /// Counts over all runs since the server started.
+    pub total: ReconciliationCounts,
+    pub last_error: Option<String>,
 }
 
 pub struct BetsSyncer {
-    url: Url,
     db_pool: PgPool,
-    provider: RootProvider<Http<Client>>,
-    gamble_contract: FloppyGambleInstance<Http<Client>, RootProvider<Http<Client>>>,
+    gamble_contract: GambleContract,
+    report: RwLock<BetsSyncReport>,
 }
 
 impl BetsSyncer {
-    pub fn new(rpc_url: String, db_pool: PgPool) -> Result<Self> {
-        let url = Url::parse(&rpc_url)?;
-        let provider = ProviderBuilder::new().on_http(url.clone());
-        let gamble_contract: FloppyGambleInstance<Http<Client>, RootProvider<Http<Client>>> =
-            FloppyGamble::new(
-                address!("ec6be1d0c53489de129b2c13ac3edb393865c22f"),
-                provider.clone(),
-            );
+    pub fn new(config: &Config, db_pool: PgPool) -> Result<Self> {
+        let provider: RootProvider<Http<Client>> =
+            ProviderBuilder::new().on_http(Url::parse(&config.rpc_url)?);
         Ok(Self {
-            url,
             db_pool,
-            provider,
-            gamble_contract,
+            gamble_contract: FloppyGamble::new(config.gamble.address, provider),
+            report: RwLock::new(BetsSyncReport::default()),
         })
     }
 
-    pub async fn run(&self) -> Result<()> {
-        println!("Starting Contract client...");
-        let mut interval = interval(Duration::from_secs(60));
+    pub fn report(&self) -> BetsSyncReport {
+        self.report.read().unwrap().clone()
+    }
+
+    pub async fn run(&self, mut worker: Worker) -> Result<()> {
+        let mut interval = interval(RECONCILE_INTERVAL);
+        while worker.tick(&mut interval).await {
+            let started_at = chrono::Utc::now().timestamp();
+            let mut counts = ReconciliationCounts::default();
+            let result = self.reconcile(&worker, &mut counts).await;
+
+            let mut report = self.report.write().unwrap();
+            report.runs += 1;
+            report.total.add(&counts);
+            report.last_run = Some(ReconciliationRun {
+                started_at,
+                finished_at: chrono::Utc::now().timestamp(),
+                counts,
+            });
+            if let Err(e) = result {
+                eprintln!("Error reconciling bets: {}", e);
+                report.last_error = Some(e.to_string());
+            }
+        }
+        Ok(())
+    }
 
+    /// Checks every unsettled bet against the contract. A bet that cannot be read is counted and
+    /// skipped, a database error ends the run, and so does shutdown between two pages.
+    async fn reconcile(&self, worker: &Worker, counts: &mut ReconciliationCounts) -> Result<()> {
+        let mut after_id = None;
         loop {
-            tokio::select! {
-                _ = interval.tick() => {
-                    if let Err(e) = self.fetch_bets().await {
-                        eprintln!("Error fetching bets: {}", e);
+            let bets = bet_record::list_unsettled_bets(&self.db_pool, after_id, PAGE_SIZE).await?;
+            let Some(last) = bets.last() else {
+                return Ok(());
+            };
+            after_id = Some(last.id);
+
+            for stored in &bets {
+                counts.checked += 1;
+                let bet_id = U256::from(stored.id as u64);
+                let on_chain = match self.gamble_contract.getBetInfoById(bet_id).call().await {
+                    Ok(bet_info) => bet_ingest::to_bet_record(bet_id, &bet_info._0)?,
+                    Err(e) => {
+                        eprintln!("Cannot read bet {} from the contract: {}", stored.id, e);
+                        counts.errors += 1;
+                        continue;
                     }
+                };
+                // Unknown bets read as zeroed structs
+                if on_chain.status == Some(BetStatus::Unknown) {
+                    counts.not_on_chain += 1;
+                    continue;
+                }
+
+                let drifts = drift(stored, &on_chain);
+                if drifts.is_empty() {
+                    continue;
                 }
+                for drift in &drifts {
+                    counts.drift.record(*drift);
+                }
+                eprintln!("Bet {} drifted from the contract: {:?}", stored.id, drifts);
+                bet_record::upsert_bet_record(&self.db_pool, &on_chain, None).await?;
+                counts.repaired += 1;
+            }
+
+            if (bets.len() as i64) < PAGE_SIZE || worker.is_stopping() {
+                return Ok(());
             }
+            worker.beat();
         }
     }
+}
 
-    async fn sync_bets_in_db(
-        &self,
-        bet_ids: Vec<alloy::primitives::Uint<256, 4>>,
-        bet_infos: Vec<IFloppyGamble::BetInfo>,
-    ) -> Result<()> {
-        let length = bet_ids.len();
-        for i in 0..length {
-            let bet_id = bet_ids[i];
-            let bet_info = bet_infos[i].clone();
-            if !bet_record::bet_exists(&self.db_pool, bet_id.to_string().parse()?).await? {
-                let bet_record = BetRecord {
-                    id: bet_id.to_string().parse()?,
-                    match_id: 0,
-                    requester_address: bet_info.requester.to_string(),
-                    receiver_address: bet_info.receiver.to_string(),
-                    bet_tier: Some(bet_info.tier.into()),
-                    bet_amount: format_units(bet_info.amount, "ether")?.parse::<f64>()?,
-                    timestamp: bet_info.timestamp.to_string().parse()?,
-                    status: Some(bet_info.status.into()),
-                    dead_line: 0,
-                };
-                bet_record::create_bet_record(&self.db_pool, bet_record).await?;
-            }
+/// Differences between a stored bet and the same bet read from the contract.
+fn drift(stored: &BetRecord, on_chain: &BetRecord) -> Vec<Drift> {
+    let mut drifts = Vec::new();
+    let stored_rank = stored.status.map(BetStatus::rank).unwrap_or_default();
+    let on_chain_rank = on_chain.status.map(BetStatus::rank).unwrap_or_default();
+    if on_chain_rank > stored_rank {
+        drifts.push(Drift::StatusBehind);
+    } else if on_chain_rank < stored_rank {
+        drifts.push(Drift::StatusAhead);
+    } else if stored.status != on_chain.status
+        || stored.points != on_chain.points
+        || stored.reward != on_chain.reward
+        || stored.win != on_chain.win
+    {
+        drifts.push(Drift::Outcome);
+    }
+    if stored.claimed.unwrap_or_default() != on_chain.claimed.unwrap_or_default() {
+        drifts.push(Drift::Claimed);
+    }
+    if !stored
+        .requester_address
+        .eq_ignore_ascii_case(&on_chain.requester_address)
+        || !stored
+            .receiver_address
+            .eq_ignore_ascii_case(&on_chain.receiver_address)
+        || stored.bet_tier != on_chain.bet_tier
+        || stored.bet_amount != on_chain.bet_amount
+        || stored.timestamp != on_chain.timestamp
+    {
+        drifts.push(Drift::Details);
+    }
+    drifts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::BetTier;
+
+    fn bet(status: BetStatus) -> BetRecord {
+        BetRecord {
+            id: 7,
+            match_id: Some(3),
+            requester_address: "0xec6Be1D0c53489dE129b2C13ac3EDb393865c22F".to_string(),
+            receiver_address: "0xec6Be1D0c53489dE129b2C13ac3EDb393865c22F".to_string(),
+            bet_tier: Some(BetTier::Gold),
+            bet_amount: "1000000000000000000".to_string(),
+            dead_line: 0,
+            timestamp: 1_700_000_000,
+            status: Some(status),
+            points: Some(0),
+            reward: Some("0".to_string()),
+            win: Some(false),
+            claimed: Some(false),
         }
-        Ok(())
     }
 
-    async fn fetch_bets(&self) -> Result<()> {
-        let result = self
-            .gamble_contract
-            .getBetsByStatus(BetStatus::Pending as u8)
-            .call()
-            .await?;
-        let bet_ids: Vec<alloy::primitives::Uint<256, 4>> = result._0;
-        let bet_infos: Vec<IFloppyGamble::BetInfo> = result._1;
+    #[test]
+    fn test_drift() {
+        let stored = bet(BetStatus::Pending);
+        let mut on_chain = bet(BetStatus::Pending);
+        on_chain.match_id = None;
+        on_chain.requester_address = on_chain.requester_address.to_lowercase();
+        assert!(drift(&stored, &on_chain).is_empty());
 
-        self.sync_bets_in_db(bet_ids, bet_infos).await?;
-        Ok(())
+        on_chain.status = Some(BetStatus::Resolved);
+        on_chain.win = Some(true);
+        on_chain.claimed = Some(true);
+        assert_eq!(
+            drift(&stored, &on_chain),
+            vec![Drift::StatusBehind, Drift::Claimed]
+        );
+        assert_eq!(drift(&on_chain, &stored)[0], Drift::StatusAhead);
+
+        let mut resolved = bet(BetStatus::Resolved);
+        resolved.points = Some(42);
+        on_chain.bet_amount = "1".to_string();
+        assert_eq!(
+            drift(&resolved, &on_chain),
+            vec![Drift::Outcome, Drift::Claimed, Drift::Details]
+        );
     }
 }
@@ -0,0 +1,352 @@
+//! Typed server configuration, loaded from a JSON file and overridden by environment variables.
+//!
+//! The file is read from `CONFIG_FILE` (default `config/ronin-testnet.json`). Any value can be
+//! overridden with the environment variables listed in [`ENV_OVERRIDES`], which is how the same
+//! binary is pointed at anvil, testnet or mainnet.
+
+use alloy::{
+    primitives::{Address, B256, U256},
+    providers::{Provider, ProviderBuilder},
+    sol,
+    sol_types::Eip712Domain,
+    transports::http::reqwest::Url,
+};
+use eyre::{bail, eyre, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+
+const DEFAULT_CONFIG_FILE: &str = "config/ronin-testnet.json";
+
+/// Environment variable, JSON path in the config file and whether the value is numeric.
+const ENV_OVERRIDES: &[(&str, &[&str], bool)] = &[
+    ("DATABASE_URL", &["database_url"], false),
+    ("RPC_URL", &["rpc_url"], false),
+    ("CHAIN_ID", &["chain_id"], true),
+    ("CONFIRMATIONS", &["confirmations"], true),
+    ("START_BLOCK", &["start_block"], true),
+    ("BIND_ADDRESS", &["bind_address"], false),
+    ("TOKEN_ADDRESS", &["token_address"], false),
+    ("GAMBLE_ADDRESS", &["gamble", "address"], false),
+    (
+        "GAMBLE_DEPLOYMENT_BLOCK",
+        &["gamble", "deployment_block"],
+        true,
+    ),
+    ("GAMBLE_DOMAIN_NAME", &["gamble", "domain_name"], false),
+    (
+        "GAMBLE_DOMAIN_VERSION",
+        &["gamble", "domain_version"],
+        false,
+    ),
+    ("VAULT_ADDRESS", &["vault", "address"], false),
+    (
+        "VAULT_DEPLOYMENT_BLOCK",
+        &["vault", "deployment_block"],
+        true,
+    ),
+    ("VAULT_DOMAIN_NAME", &["vault", "domain_name"], false),
+    ("VAULT_DOMAIN_VERSION", &["vault", "domain_version"], false),
+    ("AUTH_DOMAIN", &["auth", "domain"], false),
+    ("AUTH_SESSION_TTL", &["auth", "session_ttl"], true),
+];
+
+sol! {
+    #[allow(missing_docs)]
+    #[sol(rpc)]
+    interface IDomainSeparator {
+        function DOMAIN_SEPARATOR() external view returns (bytes32);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    pub database_url: String,
+    pub rpc_url: String,
+    pub chain_id: u64,
+    #[serde(default = "default_confirmations")]
+    pub confirmations: u64,
+    /// Block the listener starts from when no cursor is stored, the confirmed head otherwise.
+    #[serde(default)]
+    pub start_block: Option<u64>,
+    #[serde(default = "default_bind_address")]
+    pub bind_address: String,
+    pub token_address: Address,
+    pub gamble: ContractConfig,
+    pub vault: ContractConfig,
+    /// Permit signing keys. More than one is configured while rotating to a new key.
+    #[serde(default = "default_signers")]
+    pub signers: Vec<SignerConfig>,
+    #[serde(default)]
+    pub auth: AuthConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContractConfig {
+    pub address: Address,
+    pub deployment_block: u64,
+    pub domain_name: String,
+    #[serde(default = "default_domain_version")]
+    pub domain_version: String,
+    /// Starts without comparing the domain separator when the contract has no
+    /// `DOMAIN_SEPARATOR()` getter, logging a warning instead.
+    #[serde(default)]
+    pub skip_domain_separator_check: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SignerConfig {
+    /// Hex private key read from an environment variable.
+    Local {
+        #[serde(default = "default_private_key_env")]
+        private_key_env: String,
+    },
+    /// Encrypted JSON keystore whose password is read from an environment variable.
+    Keystore { path: String, password_env: String },
+    /// Remote HTTP/JSON-RPC signer, optionally pinned to an expected address.
+    Remote {
+        url: String,
+        #[serde(default)]
+        address: Option<Address>,
+    },
+}
+
+/// Sign-In with Ethereum settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Domain sign-in messages must be issued for, the host serving the game.
+    pub domain: String,
+    /// Environment variable holding the session token secret.
+    pub jwt_secret_env: String,
+    /// Lifetime of a session token, in seconds.
+    pub session_ttl: i64,
+    /// Lifetime of a sign-in nonce, in seconds.
+    pub nonce_ttl: i64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            domain: "localhost:3000".to_string(),
+            jwt_secret_env: "JWT_SECRET".to_string(),
+            session_ttl: 24 * 60 * 60,
+            nonce_ttl: 10 * 60,
+        }
+    }
+}
+
+fn default_signers() -> Vec<SignerConfig> {
+    vec![SignerConfig::Local {
+        private_key_env: default_private_key_env(),
+    }]
+}
+
+fn default_private_key_env() -> String {
+    "SIGNER_PK".to_string()
+}
+
+fn default_confirmations() -> u64 {
+    12
+}
+
+fn default_bind_address() -> String {
+    "127.0.0.1:8080".to_string()
+}
+
+fn default_domain_version() -> String {
+    "1".to_string()
+}
+
+impl Config {
+    /// Reads the config file and applies environment overrides.
+    pub fn load() -> Result<Self> {
+        let path = std::env::var("CONFIG_FILE").unwrap_or_else(|_| DEFAULT_CONFIG_FILE.to_string());
+        let mut value = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| eyre!("invalid config file {}: {}", path, e))?,
+            Err(e)
+                if e.kind() == std::io::ErrorKind::NotFound
+                    && std::env::var("CONFIG_FILE").is_err() =>
+            {
+                Value::Object(Default::default())
+            }
+            Err(e) => bail!("cannot read config file {}: {}", path, e),
+        };
+
+        for (name, path, numeric) in ENV_OVERRIDES {
+            if let Ok(raw) = std::env::var(name) {
+                let override_value = if *numeric {
+                    Value::from(
+                        raw.parse::<u64>()
+                            .map_err(|_| eyre!("{} must be a number", name))?,
+                    )
+                } else {
+                    Value::from(raw)
+                };
+                set_path(&mut value, path, override_value);
+            }
+        }
+
+        serde_json::from_value(value).map_err(|e| eyre!("invalid config: {}", e))
+    }
+
+    /// Checks the config against the chain: the RPC must serve the configured chain id and the
+    /// domain separators stored in the contracts must match the ones the signer computes.
+    pub async fn validate(&self) -> Result<()> {
+        Url::parse(&self.rpc_url).map_err(|e| eyre!("invalid rpc_url: {}", e))?;
+        self.bind_address
+            .parse::<std::net::SocketAddr>()
+            .map_err(|e| eyre!("invalid bind_address: {}", e))?;
+        if self.gamble.address == Address::ZERO || self.vault.address == Address::ZERO {
+            bail!("gamble and vault addresses must be set");
+        }
+
+        let provider = ProviderBuilder::new().on_http(Url::parse(&self.rpc_url)?);
+        let chain_id = provider.get_chain_id().await?;
+        if chain_id != self.chain_id {
+            bail!(
+                "rpc_url serves chain {} but chain_id is {}",
+                chain_id,
+                self.chain_id
+            );
+        }
+
+        for (name, contract) in [("gamble", &self.gamble), ("vault", &self.vault)] {
+            if contract.skip_domain_separator_check {
+                eprintln!(
+                    "Warning: {} DOMAIN_SEPARATOR is not checked, permits signed for a wrong domain will be rejected on chain",
+                    name
+                );
+                continue;
+            }
+            let on_chain: B256 = IDomainSeparator::new(contract.address, &provider)
+                .DOMAIN_SEPARATOR()
+                .call()
+                .await
+                .map_err(|e| {
+                    eyre!(
+                        "cannot read {} DOMAIN_SEPARATOR, set {}.skip_domain_separator_check if the contract has no getter: {}",
+                        name,
+                        name,
+                        e
+                    )
+                })?
+                ._0;
+            let expected = contract.domain(self.chain_id).separator();
+            if on_chain != expected {
+                bail!(
+                    "{} DOMAIN_SEPARATOR is {} on chain but {} in config",
+                    name,
+                    on_chain,
+                    expected
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn gamble_domain(&self) -> Eip712Domain {
+        self.gamble.domain(self.chain_id)
+    }
+
+    pub fn vault_domain(&self) -> Eip712Domain {
+        self.vault.domain(self.chain_id)
+    }
+}
+
+impl ContractConfig {
+    pub fn domain(&self, chain_id: u64) -> Eip712Domain {
+        Eip712Domain::new(
+            Some(Cow::Owned(self.domain_name.clone())),
+            Some(Cow::Owned(self.domain_version.clone())),
+            Some(U256::from(chain_id)),
+            Some(self.address),
+            None,
+        )
+    }
+}
+
+fn set_path(value: &mut Value, path: &[&str], new_value: Value) {
+    let mut current = value;
+    for key in &path[..path.len() - 1] {
+        if !current.get(key).is_some_and(Value::is_object) {
+            current[*key] = Value::Object(Default::default());
+        }
+        current = &mut current[*key];
+    }
+    current[path[path.len() - 1]] = new_value;
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use alloy::{primitives::address, sol_types::eip712_domain};
+
+    /// Config of a local chain, with the testnet contract addresses.
+    pub(crate) fn test_config() -> Config {
+        serde_json::from_value(serde_json::json!({
+            "database_url": "postgres://localhost/floppy",
+            "rpc_url": "http://localhost:8545",
+            "chain_id": 2021,
+            "token_address": "0x49B04B0fA93EbDa015DC95f8C1969F1BE3981fC5",
+            "gamble": { "address": "0xec6Be1D0c53489dE129b2C13ac3EDb393865c22F", "deployment_block": 0, "domain_name": "FloppyGamble" },
+            "vault": { "address": "0xA3378Fe70b19cB20FD278EaA60F6784bcb9372Ca", "deployment_block": 0, "domain_name": "FloppyVault" },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_set_path() {
+        let mut value = serde_json::json!({ "gamble": { "domain_name": "FloppyGamble" } });
+        set_path(&mut value, &["gamble", "deployment_block"], Value::from(10));
+        set_path(
+            &mut value,
+            &["vault", "domain_name"],
+            Value::from("FloppyVault"),
+        );
+
+        assert_eq!(value["gamble"]["domain_name"], "FloppyGamble");
+        assert_eq!(value["gamble"]["deployment_block"], 10);
+        assert_eq!(value["vault"]["domain_name"], "FloppyVault");
+    }
+
+    #[test]
+    fn test_contract_domain() {
+        let contract = ContractConfig {
+            address: address!("ec6Be1D0c53489dE129b2C13ac3EDb393865c22F"),
+            deployment_block: 30857178,
+            domain_name: "FloppyGamble".to_string(),
+            domain_version: "1".to_string(),
+            skip_domain_separator_check: false,
+        };
+        let expected = eip712_domain! {
+            name: "FloppyGamble",
+            version: "1",
+            chain_id: 2021,
+            verifying_contract: address!("ec6Be1D0c53489dE129b2C13ac3EDb393865c22F"),
+        };
+
+        assert_eq!(contract.domain(2021).separator(), expected.separator());
+    }
+
+    #[test]
+    fn test_signer_config() {
+        let signers: Vec<SignerConfig> = serde_json::from_value(serde_json::json!([
+            { "kind": "local" },
+            { "kind": "keystore", "path": "keys/signer.json", "password_env": "KEYSTORE_PASSWORD" },
+            { "kind": "remote", "url": "http://127.0.0.1:9000" },
+        ]))
+        .unwrap();
+
+        assert!(
+            matches!(&signers[0], SignerConfig::Local { private_key_env } if private_key_env == "SIGNER_PK")
+        );
+        assert!(matches!(&signers[1], SignerConfig::Keystore { .. }));
+        assert!(matches!(
+            &signers[2],
+            SignerConfig::Remote { address: None, .. }
+        ));
+    }
+}
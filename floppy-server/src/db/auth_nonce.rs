@@ -0,0 +1,35 @@
+use crate::error::Error;
+use sqlx::PgPool;
+
+pub async fn create_nonce(pool: &PgPool, nonce: &str, expires_at: i64) -> Result<(), Error> {
+    let now = chrono::Utc::now().timestamp();
+    // Expired nonces are never consumed, drop them as new ones are issued
+    sqlx::query!("DELETE FROM auth_nonce WHERE expires_at <= $1", now)
+        .execute(pool)
+        .await
+        .map_err(Error::Database)?;
+    sqlx::query!(
+        "INSERT INTO auth_nonce (nonce, expires_at) VALUES ($1, $2)",
+        nonce,
+        expires_at
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+    Ok(())
+}
+
+/// Deletes an unexpired nonce. Returns false if it was unknown, expired or already used.
+pub async fn consume_nonce(pool: &PgPool, nonce: &str) -> Result<bool, Error> {
+    let now = chrono::Utc::now().timestamp();
+    let consumed = sqlx::query!(
+        "DELETE FROM auth_nonce WHERE nonce = $1 AND expires_at > $2",
+        nonce,
+        now
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?
+    .rows_affected();
+    Ok(consumed > 0)
+}
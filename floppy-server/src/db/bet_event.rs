@@ -0,0 +1,161 @@
+use crate::error::Error;
+use crate::models::{BetEvent, BetStatus, BetTransition};
+use sqlx::{Executor, PgPool, Postgres};
+
+async fn insert_event<'e, E>(executor: E, bet_event: &BetEvent) -> Result<bool, Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    let inserted = sqlx::query!(
+        "INSERT INTO bet_event (bet_id, event_name, tx_hash, log_index, block_number, created_at) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (tx_hash, log_index) DO NOTHING",
+        bet_event.bet_id,
+        bet_event.event_name,
+        bet_event.tx_hash,
+        bet_event.log_index,
+        bet_event.block_number,
+        chrono::Utc::now().timestamp()
+    )
+    .execute(executor)
+    .await
+    .map_err(Error::Database)?
+    .rows_affected();
+    Ok(inserted > 0)
+}
+
+/// Records an event that needs no change on the bet row. Returns false if it was already recorded.
+pub async fn record_event(pool: &PgPool, bet_event: &BetEvent) -> Result<bool, Error> {
+    insert_event(pool, bet_event).await
+}
+
+/// Records the event and applies its transition in one transaction, skipping logs already applied.
+/// Like [`upsert_bet_record`](crate::db::bet_record::upsert_bet_record), a status transition never
+/// moves a bet back, so a replayed or out-of-order log leaves a later status and its outcome as is.
+pub async fn apply_transition(
+    pool: &PgPool,
+    bet_event: &BetEvent,
+    transition: BetTransition,
+) -> Result<bool, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+    if !insert_event(&mut tx, bet_event).await? {
+        return Ok(false);
+    }
+
+    match transition {
+        BetTransition::Canceled => {
+            sqlx::query!(
+                "UPDATE bet_record SET status = $1 WHERE id = $2 AND (bet_status_rank($1) > bet_status_rank(status) OR status = $1)",
+                BetStatus::Canceled.to_string(),
+                bet_event.bet_id
+            )
+            .execute(&mut tx)
+            .await
+            .map_err(Error::Database)?;
+        }
+        BetTransition::Resolved {
+            win,
+            points,
+            reward,
+        } => {
+            sqlx::query!(
+                "UPDATE bet_record SET status = $1, win = $2, points = $3, reward = $4::TEXT::NUMERIC WHERE id = $5 AND (bet_status_rank($1) > bet_status_rank(status) OR status = $1)",
+                BetStatus::Resolved.to_string(),
+                win,
+                points,
+                reward,
+                bet_event.bet_id
+            )
+            .execute(&mut tx)
+            .await
+            .map_err(Error::Database)?;
+        }
+        BetTransition::Claimed => {
+            sqlx::query!(
+                "UPDATE bet_record SET claimed = TRUE WHERE id = $1",
+                bet_event.bet_id
+            )
+            .execute(&mut tx)
+            .await
+            .map_err(Error::Database)?;
+        }
+    }
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(true)
+}
+
+/// Finds the oldest won, unclaimed bet of `receiver` whose reward equals `amount`.
+pub async fn find_unclaimed_bet_id(
+    pool: &PgPool,
+    receiver: &str,
+    amount: &str,
+) -> Result<Option<i64>, Error> {
+    sqlx::query_scalar!(
+        "SELECT id FROM bet_record WHERE LOWER(receiver_address) = LOWER($1) AND status = $2 AND win AND NOT claimed AND reward = $3::TEXT::NUMERIC ORDER BY id LIMIT 1",
+        receiver,
+        BetStatus::Resolved.to_string(),
+        amount
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::bet_record;
+
+    fn event(bet_id: i64, event_name: &str, log_index: i64) -> BetEvent {
+        BetEvent {
+            bet_id,
+            event_name: event_name.to_string(),
+            tx_hash: format!("0x{}", log_index),
+            log_index,
+            block_number: 10 + log_index,
+        }
+    }
+
+    fn resolved(points: i64) -> BetTransition {
+        BetTransition::Resolved {
+            win: true,
+            points,
+            reward: "20".to_string(),
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_apply_transition_never_moves_back(pool: PgPool) {
+        sqlx::query(
+            "INSERT INTO bet_record (id, requester_address, receiver_address, bet_amount, dead_line, timestamp, status)
+            VALUES (1, '0xa', '0xa', 10, 0, 0, 'Pending'), (2, '0xa', '0xa', 10, 0, 0, 'Pending')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        assert!(
+            apply_transition(&pool, &event(1, "BetResolved", 2), resolved(7))
+                .await
+                .unwrap()
+        );
+        // An older or replayed log applied after the resolution
+        apply_transition(&pool, &event(1, "BetCanceled", 1), BetTransition::Canceled)
+            .await
+            .unwrap();
+        let bet = bet_record::get_bet_record_by_id(&pool, 1).await.unwrap();
+        assert_eq!(bet.status, Some(BetStatus::Resolved));
+        assert_eq!(bet.points, Some(7));
+        assert_eq!(bet.win, Some(true));
+
+        apply_transition(&pool, &event(2, "BetCanceled", 3), BetTransition::Canceled)
+            .await
+            .unwrap();
+        apply_transition(&pool, &event(2, "BetResolved", 4), resolved(9))
+            .await
+            .unwrap();
+        let bet = bet_record::get_bet_record_by_id(&pool, 2).await.unwrap();
+        assert_eq!(bet.status, Some(BetStatus::Canceled));
+        assert_eq!(bet.points, None);
+        assert_eq!(bet.win, None);
+    }
+}
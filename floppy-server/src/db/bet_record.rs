@@ -1,66 +1,111 @@
 use crate::error::Error;
-use crate::models::{BetRecord, BetStatus, BetTier};
-use sqlx::PgPool;
+use crate::models::{BetRecord, BetStatus, BetTier, RecordFilter};
+use sqlx::{PgConnection, PgPool};
 
 pub async fn get_bet_record_by_id(pool: &PgPool, bet_id: i64) -> Result<BetRecord, Error> {
     sqlx::query_as!(
         BetRecord,
-        "SELECT id, match_id, requester_address, receiver_address, bet_tier AS \"bet_tier: BetTier\", bet_amount, dead_line, timestamp, status AS \"status: BetStatus\" FROM bet_record WHERE id = $1",
+        "SELECT id, match_id, requester_address, receiver_address, bet_tier AS \"bet_tier: BetTier\", bet_amount::TEXT AS \"bet_amount!\", dead_line, timestamp, status AS \"status: BetStatus\", points, reward::TEXT AS reward, win, claimed FROM bet_record WHERE id = $1",
         bet_id
     )
     .fetch_one(pool)
     .await
     .map_err(|e| match e {
-        sqlx::Error::RowNotFound => Error::NotFound,
+        sqlx::Error::RowNotFound => Error::NotFound("bet"),
         _ => Error::Database(e),
     })
 }
 
-pub async fn get_all_pending_bet_ids(pool: &PgPool) -> Result<Vec<i64>, Error> {
-    sqlx::query!(
-        "SELECT id FROM bet_record WHERE status = $1",
-        BetStatus::Pending.to_string()
+/// Lists up to `limit` bets after `after_id` whose on-chain state can still change: not yet
+/// resolved or canceled, or won and not claimed yet. Starts at the first bet, id 0, without
+/// `after_id`.
+pub async fn list_unsettled_bets(
+    pool: &PgPool,
+    after_id: Option<i64>,
+    limit: i64,
+) -> Result<Vec<BetRecord>, Error> {
+    sqlx::query_as!(
+        BetRecord,
+        "SELECT id, match_id, requester_address, receiver_address, bet_tier AS \"bet_tier: BetTier\", bet_amount::TEXT AS \"bet_amount!\", dead_line, timestamp, status AS \"status: BetStatus\", points, reward::TEXT AS reward, win, claimed FROM bet_record
+        WHERE ($1::BIGINT IS NULL OR id > $1) AND (bet_status_rank(status) < 2 OR (status = $2 AND win AND NOT COALESCE(claimed, FALSE)))
+        ORDER BY id LIMIT $3",
+        after_id,
+        BetStatus::Resolved.to_string(),
+        limit
     )
     .fetch_all(pool)
     .await
-    .map(|rows| rows.into_iter().map(|row| row.id).collect())
     .map_err(Error::Database)
 }
 
-// pub async fn get_all_match_records(pool: &PgPool) -> Result<Vec<MatchRecord>, Error> {
-//     sqlx::query_as!(
-//         MatchRecord,
-//         "SELECT id, wallet_id, start_time, end_time, play_data, player_point, status AS \"status: MatchStatus\" FROM match_record"
-//     )
-//     .fetch_all(pool)
-//     .await
-//     .map_err(|e| match e {
-//         sqlx::Error::RowNotFound => Error::NotFound,
-//         _ => Error::Database(e),
-//     })
-// }
+/// Lists up to `limit` bets passing `filter`, in its order and after its cursor.
+pub async fn list_bet_records(
+    pool: &PgPool,
+    filter: &RecordFilter,
+    limit: i64,
+) -> Result<Vec<BetRecord>, Error> {
+    let cursor = filter.cursor.as_ref();
+    sqlx::query_as!(
+        BetRecord,
+        "WITH filtered AS (
+            SELECT b.*, CASE $7
+                WHEN 'time' THEN b.timestamp::NUMERIC
+                WHEN 'points' THEN COALESCE(b.points, 0)::NUMERIC
+                WHEN 'amount' THEN b.bet_amount
+                ELSE b.id::NUMERIC END AS sort_key
+            FROM bet_record b
+            WHERE ($1::TEXT IS NULL OR LOWER(b.requester_address) = LOWER($1))
+                AND ($2::TEXT IS NULL OR b.status = $2)
+                AND ($3::TEXT IS NULL OR b.bet_tier = $3)
+                AND ($4::BIGINT IS NULL OR b.match_id = $4)
+                AND ($5::BIGINT IS NULL OR b.timestamp >= $5)
+                AND ($6::BIGINT IS NULL OR b.timestamp < $6)
+        )
+        SELECT id AS \"id!\", match_id, requester_address AS \"requester_address!\", receiver_address AS \"receiver_address!\", bet_tier AS \"bet_tier: BetTier\", bet_amount::TEXT AS \"bet_amount!\", dead_line AS \"dead_line!\", timestamp AS \"timestamp!\", status AS \"status: BetStatus\", points, reward::TEXT AS reward, win, claimed
+        FROM filtered
+        WHERE $9::TEXT IS NULL OR ($8::INT * sort_key, $8::INT * id) > ($8::INT * $9::TEXT::NUMERIC, $8::INT * $10::BIGINT)
+        ORDER BY $8::INT * sort_key, $8::INT * id
+        LIMIT $11",
+        filter.wallet,
+        filter.status,
+        filter.tier,
+        filter.match_id,
+        filter.from,
+        filter.to,
+        filter.sort_name(),
+        filter.direction(),
+        cursor.map(|cursor| cursor.sort_value.clone()),
+        cursor.map(|cursor| cursor.id),
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
 
-pub async fn create_bet_record(pool: &PgPool, bet_record: BetRecord) -> Result<(), Error> {
-    sqlx::query!(
-        "INSERT INTO bet_record (id, match_id, requester_address, receiver_address, bet_tier, bet_amount, dead_line, timestamp, status) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id ",
-        bet_record.id,
-        bet_record.match_id as i32,
-        bet_record.requester_address,
-        bet_record.receiver_address,
-        bet_record.bet_tier.map(|s| s.to_string()).unwrap_or_default(),
-        bet_record.bet_amount,
-        bet_record.dead_line,
-        bet_record.timestamp,
-        bet_record.status.map(|s| s.to_string()).unwrap_or_default()
+/// Number of bets passing `filter`, ignoring its cursor.
+pub async fn count_bet_records(pool: &PgPool, filter: &RecordFilter) -> Result<i64, Error> {
+    sqlx::query_scalar!(
+        "SELECT COUNT(*) AS \"total!\" FROM bet_record b
+        WHERE ($1::TEXT IS NULL OR LOWER(b.requester_address) = LOWER($1))
+            AND ($2::TEXT IS NULL OR b.status = $2)
+            AND ($3::TEXT IS NULL OR b.bet_tier = $3)
+            AND ($4::BIGINT IS NULL OR b.match_id = $4)
+            AND ($5::BIGINT IS NULL OR b.timestamp >= $5)
+            AND ($6::BIGINT IS NULL OR b.timestamp < $6)",
+        filter.wallet,
+        filter.status,
+        filter.tier,
+        filter.match_id,
+        filter.from,
+        filter.to
     )
     .fetch_one(pool)
     .await
-    .map_err(Error::Database)?;
-
-    Ok(())
+    .map_err(Error::Database)
 }
 
-pub async fn bet_exists(pool: &PgPool, bet_id: i64) -> Result<bool, Error> {
+pub async fn is_bet_exists(pool: &PgPool, bet_id: i64) -> Result<bool, Error> {
     let result = sqlx::query!(
         "SELECT EXISTS(SELECT 1 FROM bet_record WHERE id = $1)",
         bet_id
@@ -72,61 +117,168 @@ pub async fn bet_exists(pool: &PgPool, bet_id: i64) -> Result<bool, Error> {
     Ok(result.exists.unwrap_or(false))
 }
 
-pub async fn is_bet_exists(pool: &PgPool, bet_id: i64) -> Result<bool, Error> {
-    let result = sqlx::query!(
-        "SELECT EXISTS(SELECT 1 FROM bet_record WHERE id = $1)",
+/// Serializes linking and indexing of bet `bet_id`, which may run before the bet row exists and
+/// so cannot rely on row locks alone. Held until the transaction ends.
+async fn lock_bet(conn: &mut PgConnection, bet_id: i64) -> Result<(), Error> {
+    sqlx::query!(
+        "SELECT 1 AS \"locked!\" FROM pg_advisory_xact_lock($1)",
         bet_id
     )
-    .fetch_one(pool)
+    .fetch_one(conn)
     .await
     .map_err(Error::Database)?;
-
-    Ok(result.exists.unwrap_or(false))
+    Ok(())
 }
 
-pub async fn is_bet_exists_in_match(
-    pool: &PgPool,
+/// Links bet `bet_id` of `wallet_id` to match `match_id` within the caller's transaction. A bet
+/// not indexed yet gets a pending link, completed by [`upsert_bet_record`] when it is indexed.
+/// Relinking to the same match is a no-op, to another match a conflict.
+pub async fn link_match(
+    conn: &mut PgConnection,
     bet_id: i64,
     match_id: i64,
-) -> Result<bool, Error> {
-    let result = sqlx::query!(
-        "SELECT EXISTS(SELECT 1 FROM bet_record WHERE id = $1 AND match_id = $2)",
+    wallet_id: &str,
+) -> Result<(), Error> {
+    lock_bet(conn, bet_id).await?;
+    let bet = sqlx::query!(
+        "SELECT LOWER(requester_address) = LOWER($2) AS \"is_requester!\", match_id FROM bet_record WHERE id = $1 FOR UPDATE",
         bet_id,
-        match_id
+        wallet_id
     )
-    .fetch_one(pool)
+    .fetch_optional(&mut *conn)
     .await
     .map_err(Error::Database)?;
 
-    Ok(result.exists.unwrap_or(false))
+    let linked = match &bet {
+        Some(bet) if !bet.is_requester => {
+            return Err(Error::Forbidden(
+                "bet was not placed by this wallet".to_string(),
+            ))
+        }
+        Some(bet) => bet.match_id,
+        None => sqlx::query_scalar!(
+            "SELECT match_id FROM pending_match_link WHERE bet_id = $1 AND LOWER(wallet_id) = LOWER($2) FOR UPDATE",
+            bet_id,
+            wallet_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(Error::Database)?,
+    };
+    match linked {
+        Some(linked) if linked == match_id => return Ok(()),
+        Some(_) => {
+            return Err(Error::Conflict(
+                "bet is already linked to another match".to_string(),
+            ))
+        }
+        None => (),
+    }
+
+    if bet.is_some() {
+        sqlx::query!(
+            "UPDATE bet_record SET match_id = $1 WHERE id = $2",
+            match_id,
+            bet_id
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(Error::Database)?;
+    } else {
+        sqlx::query!(
+            "INSERT INTO pending_match_link (bet_id, wallet_id, match_id, created_at) VALUES ($1, $2, $3, $4)",
+            bet_id,
+            wallet_id,
+            match_id,
+            chrono::Utc::now().timestamp()
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(Error::Database)?;
+    }
+    Ok(())
 }
 
-pub async fn update_bet_record(pool: &PgPool, bet_record: BetRecord) -> Result<(), Error> {
+/// Links a freshly stored bet to the match its requester asked for before it was indexed.
+async fn complete_pending_link(conn: &mut PgConnection, bet_id: i64) -> Result<(), Error> {
     sqlx::query!(
-        "UPDATE bet_record SET requester_address = $1, receiver_address = $2, bet_tier = $3, bet_amount = $4, dead_line = $5, timestamp = $6, status = $7 WHERE id = $8",
-        bet_record.requester_address,
-        bet_record.receiver_address,
-        bet_record.bet_tier.map(|s| s.to_string()).unwrap_or_default(),
-        bet_record.bet_amount,
-        bet_record.dead_line,
-        bet_record.timestamp,
-        bet_record.status.map(|s| s.to_string()).unwrap_or_default(),
-        bet_record.id
+        "UPDATE bet_record SET match_id = p.match_id FROM pending_match_link p WHERE bet_record.id = $1 AND bet_record.match_id IS NULL AND p.bet_id = bet_record.id AND LOWER(p.wallet_id) = LOWER(bet_record.requester_address)",
+        bet_id
     )
-    .execute(pool)
+    .execute(&mut *conn)
     .await
     .map_err(Error::Database)?;
+    sqlx::query!("DELETE FROM pending_match_link WHERE bet_id = $1", bet_id)
+        .execute(conn)
+        .await
+        .map_err(Error::Database)?;
     Ok(())
 }
 
-pub async fn update_match_id(pool: &PgPool, bet_id: i64, match_id: i64) -> Result<(), Error> {
+/// Merges the on-chain state of a bet into its row, creating it if needed. Safe to repeat and to
+/// run with stale reads: the status never moves back and the outcome is only taken along with
+/// its status, while the off-chain match link and deadline are left untouched.
+pub async fn upsert_bet_record(
+    pool: &PgPool,
+    bet_record: &BetRecord,
+    block_number: Option<i64>,
+) -> Result<(), Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+    lock_bet(&mut tx, bet_record.id).await?;
     sqlx::query!(
-        "UPDATE bet_record SET match_id = $1 WHERE id = $2",
-        match_id,
-        bet_id
+        "INSERT INTO bet_record (id, requester_address, receiver_address, bet_tier, bet_amount, dead_line, timestamp, status, points, reward, win, claimed, block_number)
+        VALUES ($1, $2, $3, COALESCE($4, 'Unknown'), $5::TEXT::NUMERIC, 0, $6, COALESCE($7, 'Unknown'), $8, $9::TEXT::NUMERIC, $10, $11, $12)
+        ON CONFLICT (id) DO UPDATE SET
+            requester_address = EXCLUDED.requester_address,
+            receiver_address = EXCLUDED.receiver_address,
+            bet_tier = EXCLUDED.bet_tier,
+            bet_amount = EXCLUDED.bet_amount,
+            timestamp = EXCLUDED.timestamp,
+            status = CASE WHEN bet_status_rank(EXCLUDED.status) > bet_status_rank(bet_record.status) THEN EXCLUDED.status ELSE bet_record.status END,
+            points = CASE WHEN bet_status_rank(EXCLUDED.status) >= bet_status_rank(bet_record.status) THEN EXCLUDED.points ELSE bet_record.points END,
+            reward = CASE WHEN bet_status_rank(EXCLUDED.status) >= bet_status_rank(bet_record.status) THEN EXCLUDED.reward ELSE bet_record.reward END,
+            win = CASE WHEN bet_status_rank(EXCLUDED.status) >= bet_status_rank(bet_record.status) THEN EXCLUDED.win ELSE bet_record.win END,
+            claimed = COALESCE(bet_record.claimed, FALSE) OR EXCLUDED.claimed,
+            block_number = COALESCE(EXCLUDED.block_number, bet_record.block_number)",
+        bet_record.id,
+        bet_record.requester_address,
+        bet_record.receiver_address,
+        bet_record.bet_tier.map(|s| s.to_string()),
+        bet_record.bet_amount,
+        bet_record.timestamp,
+        bet_record.status.map(|s| s.to_string()),
+        bet_record.points,
+        bet_record.reward,
+        bet_record.win,
+        bet_record.claimed.unwrap_or_default(),
+        block_number
     )
-    .execute(pool)
+    .execute(&mut tx)
     .await
     .map_err(Error::Database)?;
-    Ok(())
+    complete_pending_link(&mut tx, bet_record.id).await?;
+    tx.commit().await.map_err(Error::Database)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_list_unsettled_bets(pool: PgPool) {
+        // The contract numbers bets from 0
+        sqlx::query(
+            "INSERT INTO bet_record (id, requester_address, receiver_address, bet_amount, dead_line, timestamp, status)
+            VALUES (0, '0xa', '0xa', 1, 0, 0, 'Pending'), (1, '0xa', '0xa', 1, 0, 0, 'Canceled'), (2, '0xa', '0xa', 1, 0, 0, 'Unknown')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let ids = |bets: Vec<BetRecord>| bets.into_iter().map(|bet| bet.id).collect::<Vec<_>>();
+        let first = list_unsettled_bets(&pool, None, 1).await.unwrap();
+        assert_eq!(ids(first), vec![0]);
+        let rest = list_unsettled_bets(&pool, Some(0), 10).await.unwrap();
+        assert_eq!(ids(rest), vec![2]);
+    }
 }
@@ -0,0 +1,43 @@
+use crate::error::Error;
+use crate::models::GamblePermitRecord;
+use sqlx::PgPool;
+
+pub async fn get_gamble_permit(
+    pool: &PgPool,
+    bet_id: i64,
+) -> Result<Option<GamblePermitRecord>, Error> {
+    sqlx::query_as!(
+        GamblePermitRecord,
+        "SELECT bet_id, match_id, requester, receiver, points, bet_amount::TEXT AS \"bet_amount!\", deadline, signature FROM gamble_permit WHERE bet_id = $1",
+        bet_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+/// Stores the permit issued for a bet. A bet already signed is only re-signed once its permit has
+/// expired and for the same match and points; returns false otherwise.
+pub async fn save_gamble_permit(pool: &PgPool, permit: &GamblePermitRecord) -> Result<bool, Error> {
+    let now = chrono::Utc::now().timestamp();
+    let saved = sqlx::query!(
+        "INSERT INTO gamble_permit (bet_id, match_id, requester, receiver, points, bet_amount, deadline, signature, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6::TEXT::NUMERIC, $7, $8, $9)
+        ON CONFLICT (bet_id) DO UPDATE SET bet_amount = EXCLUDED.bet_amount, deadline = EXCLUDED.deadline, signature = EXCLUDED.signature, created_at = EXCLUDED.created_at
+        WHERE gamble_permit.match_id = EXCLUDED.match_id AND gamble_permit.points = EXCLUDED.points AND gamble_permit.deadline <= $9",
+        permit.bet_id,
+        permit.match_id,
+        permit.requester,
+        permit.receiver,
+        permit.points,
+        permit.bet_amount,
+        permit.deadline,
+        permit.signature,
+        now
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?
+    .rows_affected();
+    Ok(saved > 0)
+}
@@ -0,0 +1,204 @@
+use crate::error::Error;
+use crate::models::IndexerCursor;
+use sqlx::PgPool;
+
+pub async fn get_cursor(pool: &PgPool, name: &str) -> Result<Option<IndexerCursor>, Error> {
+    sqlx::query_as!(
+        IndexerCursor,
+        "SELECT name, block_number, block_hash FROM indexer_cursor WHERE name = $1 ORDER BY block_number DESC LIMIT 1",
+        name
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+pub async fn get_checkpoints(pool: &PgPool, name: &str) -> Result<Vec<IndexerCursor>, Error> {
+    sqlx::query_as!(
+        IndexerCursor,
+        "SELECT name, block_number, block_hash FROM indexer_cursor WHERE name = $1 ORDER BY block_number DESC",
+        name
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+/// Records a processed block and prunes checkpoints older than `history` blocks.
+pub async fn save_cursor(
+    pool: &PgPool,
+    name: &str,
+    block_number: i64,
+    block_hash: &str,
+    history: i64,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "INSERT INTO indexer_cursor (name, block_number, block_hash, updated_at) VALUES ($1, $2, $3, $4) ON CONFLICT (name, block_number) DO UPDATE SET block_hash = EXCLUDED.block_hash, updated_at = EXCLUDED.updated_at",
+        name,
+        block_number,
+        block_hash,
+        chrono::Utc::now().timestamp()
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+
+    sqlx::query!(
+        "DELETE FROM indexer_cursor WHERE name = $1 AND block_number < $2",
+        name,
+        block_number - history
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+    Ok(())
+}
+
+/// Rewinds the cursor to `block_number`, deleting bet events and vault transactions from later
+/// blocks. Bets placed in later blocks are kept for their issued permits, with their on-chain state
+/// reset and their match link left pending until they are indexed again for the same requester.
+/// Returns the ids of the orphaned bets and of the bets touched by orphaned events, reset to an
+/// unknown status, which must be re-synced.
+pub async fn rollback_to(pool: &PgPool, name: &str, block_number: i64) -> Result<Vec<i64>, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+
+    sqlx::query!(
+        "DELETE FROM indexer_cursor WHERE name = $1 AND block_number > $2",
+        name,
+        block_number
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    let touched_bet_ids = sqlx::query_scalar!(
+        "DELETE FROM bet_event WHERE block_number > $1 RETURNING bet_id",
+        block_number
+    )
+    .fetch_all(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    // The bet id may be taken by another requester's bet on the new chain
+    sqlx::query!(
+        "INSERT INTO pending_match_link (bet_id, wallet_id, match_id, created_at)
+        SELECT id, requester_address, match_id, $2 FROM bet_record WHERE block_number > $1 AND match_id IS NOT NULL
+        ON CONFLICT (bet_id, wallet_id) DO NOTHING",
+        block_number,
+        chrono::Utc::now().timestamp()
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    let mut bet_ids = sqlx::query_scalar!(
+        "UPDATE bet_record SET match_id = NULL, status = 'Unknown', points = NULL, reward = NULL, win = NULL, claimed = FALSE, block_number = NULL
+        WHERE block_number > $1 RETURNING id",
+        block_number
+    )
+    .fetch_all(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    sqlx::query!(
+        "DELETE FROM vault_transaction WHERE block_number > $1",
+        block_number
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    // Bet ingestion never moves a bet back, so the on-chain state of the re-synced bets is reset
+    let touched_bet_ids = sqlx::query_scalar!(
+        "UPDATE bet_record SET status = 'Unknown', claimed = FALSE WHERE id = ANY($1) RETURNING id",
+        &touched_bet_ids
+    )
+    .fetch_all(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    tx.commit().await.map_err(Error::Database)?;
+    bet_ids.extend(touched_bet_ids);
+    bet_ids.sort_unstable();
+    bet_ids.dedup();
+    Ok(bet_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{bet_record, gamble_permit};
+    use crate::models::{BetRecord, BetStatus, GamblePermitRecord};
+
+    fn bet(status: BetStatus) -> BetRecord {
+        BetRecord {
+            id: 4,
+            match_id: None,
+            requester_address: "0xaa".to_string(),
+            receiver_address: "0xaa".to_string(),
+            bet_tier: None,
+            bet_amount: "10".to_string(),
+            dead_line: 0,
+            timestamp: 0,
+            status: Some(status),
+            points: Some(0),
+            reward: Some("0".to_string()),
+            win: Some(false),
+            claimed: Some(false),
+        }
+    }
+
+    fn permit(points: i64) -> GamblePermitRecord {
+        GamblePermitRecord {
+            bet_id: 4,
+            match_id: 1,
+            requester: "0xaa".to_string(),
+            receiver: "0xaa".to_string(),
+            points,
+            bet_amount: "10".to_string(),
+            // Expired, so only another result keeps it from being re-signed
+            deadline: 0,
+            signature: "0x".to_string(),
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_rollback_keeps_permits(pool: PgPool) {
+        sqlx::query(
+            "INSERT INTO match_record (id, wallet_id, status) VALUES (1, '0xaa', 'OffMatch')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        bet_record::upsert_bet_record(&pool, &bet(BetStatus::Pending), Some(10))
+            .await
+            .unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        bet_record::link_match(&mut conn, 4, 1, "0xaa")
+            .await
+            .unwrap();
+        drop(conn);
+        assert!(gamble_permit::save_gamble_permit(&pool, &permit(7))
+            .await
+            .unwrap());
+
+        assert_eq!(rollback_to(&pool, "test", 5).await.unwrap(), vec![4]);
+        let orphaned = bet_record::get_bet_record_by_id(&pool, 4).await.unwrap();
+        assert_eq!(orphaned.status, Some(BetStatus::Unknown));
+        assert_eq!(orphaned.match_id, None);
+
+        // Placed again on the new chain
+        bet_record::upsert_bet_record(&pool, &bet(BetStatus::Pending), Some(12))
+            .await
+            .unwrap();
+        let reindexed = bet_record::get_bet_record_by_id(&pool, 4).await.unwrap();
+        assert_eq!(reindexed.match_id, Some(1));
+        assert!(gamble_permit::get_gamble_permit(&pool, 4)
+            .await
+            .unwrap()
+            .is_some());
+        assert!(!gamble_permit::save_gamble_permit(&pool, &permit(9))
+            .await
+            .unwrap());
+    }
+}
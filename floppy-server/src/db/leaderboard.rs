@@ -0,0 +1,88 @@
+use crate::error::Error;
+use crate::models::{BetTier, LeaderboardEntry, MatchStatus};
+use sqlx::PgPool;
+
+/// Ranks wallets by their best match ended at or after `since`, counting only matches played
+/// for a bet of `tier` if given. Ties share a rank and are listed by who scored first.
+pub async fn get_leaderboard(
+    pool: &PgPool,
+    since: i64,
+    tier: Option<BetTier>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<LeaderboardEntry>, Error> {
+    sqlx::query_as!(
+        LeaderboardEntry,
+        "WITH best AS (
+            SELECT DISTINCT ON (LOWER(m.wallet_id)) m.id, m.wallet_id, m.player_point, m.end_time
+            FROM match_record m
+            WHERE m.status = $1 AND m.wallet_id IS NOT NULL AND m.end_time >= $2
+                AND ($3::TEXT IS NULL OR EXISTS (SELECT 1 FROM bet_record b WHERE b.match_id = m.id AND b.bet_tier = $3))
+            ORDER BY LOWER(m.wallet_id), m.player_point DESC NULLS LAST, m.end_time ASC, m.id ASC
+        )
+        SELECT RANK() OVER (ORDER BY COALESCE(player_point, 0) DESC) AS \"rank!\", wallet_id AS \"wallet_id!\", COALESCE(player_point, 0) AS \"points!\", id AS match_id, end_time AS \"achieved_at!\"
+        FROM best
+        ORDER BY 1, end_time ASC, id ASC
+        LIMIT $4 OFFSET $5",
+        MatchStatus::OffMatch.to_string(),
+        since,
+        tier.map(|tier| tier.to_string()),
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+/// Number of wallets ranked by [`get_leaderboard`] with the same filters.
+pub async fn count_leaderboard(
+    pool: &PgPool,
+    since: i64,
+    tier: Option<BetTier>,
+) -> Result<i64, Error> {
+    sqlx::query_scalar!(
+        "SELECT COUNT(DISTINCT LOWER(m.wallet_id)) AS \"total!\"
+        FROM match_record m
+        WHERE m.status = $1 AND m.wallet_id IS NOT NULL AND m.end_time >= $2
+            AND ($3::TEXT IS NULL OR EXISTS (SELECT 1 FROM bet_record b WHERE b.match_id = m.id AND b.bet_tier = $3))",
+        MatchStatus::OffMatch.to_string(),
+        since,
+        tier.map(|tier| tier.to_string())
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_get_leaderboard(pool: PgPool) {
+        for (wallet_id, player_point, end_time) in [
+            ("0xaa", None, 10),
+            ("0xAA", Some(5), 20),
+            ("0xbb", Some(7), 30),
+        ] {
+            sqlx::query("INSERT INTO match_record (wallet_id, start_time, end_time, player_point, status) VALUES ($1, 0, $2, $3, $4)")
+                .bind(wallet_id)
+                .bind(end_time as i64)
+                .bind(player_point)
+                .bind(MatchStatus::OffMatch.to_string())
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let entries = get_leaderboard(&pool, 0, None, 10, 0).await.unwrap();
+        let ranks: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.rank, entry.wallet_id.as_str(), entry.points))
+            .collect();
+        // The match without points does not hide the best one
+        assert_eq!(ranks, vec![(1, "0xbb", 7), (2, "0xAA", 5)]);
+        assert_eq!(count_leaderboard(&pool, 0, None).await.unwrap(), 2);
+    }
+}
@@ -1,24 +1,25 @@
+use crate::db::bet_record;
 use crate::error::Error;
-use crate::models::{MatchRecord, MatchStatus};
+use crate::models::{MatchRecord, MatchStatus, RecordFilter};
 use sqlx::PgPool;
 
-pub async fn get_match_by_id(pool: &PgPool, match_id: i32) -> Result<MatchRecord, Error> {
+pub async fn get_match_by_id(pool: &PgPool, match_id: i64) -> Result<MatchRecord, Error> {
     sqlx::query_as!(
         MatchRecord,
-        "SELECT id, wallet_id, start_time, end_time, play_data, player_point, status AS \"status: MatchStatus\" FROM match_record WHERE id = $1",
+        "SELECT id, wallet_id, start_time, end_time, play_data, player_point, status AS \"status: MatchStatus\", session_id, seed FROM match_record WHERE id = $1",
         match_id
     )
     .fetch_one(pool)
     .await
     .map_err(|e| match e {
-        sqlx::Error::RowNotFound => Error::NotFound,
+        sqlx::Error::RowNotFound => Error::NotFound("match"),
         _ => Error::Database(e),
     })
 }
 
 pub async fn get_player_point_by_match_id(
     pool: &PgPool,
-    match_id: i32,
+    match_id: i64,
 ) -> Result<Option<i32>, Error> {
     let point = sqlx::query_scalar!(
         "SELECT player_point FROM match_record WHERE id = $1",
@@ -26,67 +27,149 @@ pub async fn get_player_point_by_match_id(
     )
     .fetch_one(pool)
     .await
-    .map_err(Error::Database)?;
+    .map_err(|e| match e {
+        sqlx::Error::RowNotFound => Error::NotFound("match"),
+        _ => Error::Database(e),
+    })?;
     Ok(point)
 }
 
-pub async fn get_all_match_records(pool: &PgPool) -> Result<Vec<MatchRecord>, Error> {
+/// Lists up to `limit` matches passing `filter`, in its order and after its cursor. The tier
+/// filter keeps matches played for a bet of that tier.
+pub async fn list_match_records(
+    pool: &PgPool,
+    filter: &RecordFilter,
+    limit: i64,
+) -> Result<Vec<MatchRecord>, Error> {
+    let cursor = filter.cursor.as_ref();
     sqlx::query_as!(
         MatchRecord,
-        "SELECT id, wallet_id, start_time, end_time, play_data, player_point, status AS \"status: MatchStatus\" FROM match_record"
+        "WITH filtered AS (
+            SELECT m.*, CASE $7
+                WHEN 'time' THEN COALESCE(m.start_time, 0)::NUMERIC
+                WHEN 'points' THEN COALESCE(m.player_point, 0)::NUMERIC
+                ELSE m.id::NUMERIC END AS sort_key
+            FROM match_record m
+            WHERE ($1::TEXT IS NULL OR LOWER(m.wallet_id) = LOWER($1))
+                AND ($2::TEXT IS NULL OR m.status = $2)
+                AND ($3::TEXT IS NULL OR EXISTS (SELECT 1 FROM bet_record b WHERE b.match_id = m.id AND b.bet_tier = $3))
+                AND ($4::BIGINT IS NULL OR m.id = $4)
+                AND ($5::BIGINT IS NULL OR m.start_time >= $5)
+                AND ($6::BIGINT IS NULL OR m.start_time < $6)
+        )
+        SELECT id AS \"id!\", wallet_id, start_time, end_time, play_data, player_point, status AS \"status: MatchStatus\", session_id, seed
+        FROM filtered
+        WHERE $9::TEXT IS NULL OR ($8::INT * sort_key, $8::INT * id) > ($8::INT * $9::TEXT::NUMERIC, $8::INT * $10::BIGINT)
+        ORDER BY $8::INT * sort_key, $8::INT * id
+        LIMIT $11",
+        filter.wallet,
+        filter.status,
+        filter.tier,
+        filter.match_id,
+        filter.from,
+        filter.to,
+        filter.sort_name(),
+        filter.direction(),
+        cursor.map(|cursor| cursor.sort_value.clone()),
+        cursor.map(|cursor| cursor.id),
+        limit
     )
     .fetch_all(pool)
     .await
-    .map_err(|e| match e {
-        sqlx::Error::RowNotFound => Error::NotFound,
-        _ => Error::Database(e),
-    })
+    .map_err(Error::Database)
 }
 
-pub async fn create_match_record(pool: &PgPool, match_record: MatchRecord) -> Result<i32, Error> {
-    let result = sqlx::query!(
-        "INSERT INTO match_record (wallet_id, start_time, end_time, play_data, player_point, status) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id ",
-        match_record.wallet_id,
-        match_record.start_time,
-        match_record.end_time,
-        match_record.play_data,
-        match_record.player_point,
-        match_record.status.map(|s| s.to_string()).unwrap_or_default()
+/// Number of matches passing `filter`, ignoring its cursor.
+pub async fn count_match_records(pool: &PgPool, filter: &RecordFilter) -> Result<i64, Error> {
+    sqlx::query_scalar!(
+        "SELECT COUNT(*) AS \"total!\" FROM match_record m
+        WHERE ($1::TEXT IS NULL OR LOWER(m.wallet_id) = LOWER($1))
+            AND ($2::TEXT IS NULL OR m.status = $2)
+            AND ($3::TEXT IS NULL OR EXISTS (SELECT 1 FROM bet_record b WHERE b.match_id = m.id AND b.bet_tier = $3))
+            AND ($4::BIGINT IS NULL OR m.id = $4)
+            AND ($5::BIGINT IS NULL OR m.start_time >= $5)
+            AND ($6::BIGINT IS NULL OR m.start_time < $6)",
+        filter.wallet,
+        filter.status,
+        filter.tier,
+        filter.match_id,
+        filter.from,
+        filter.to
     )
     .fetch_one(pool)
     .await
-    .map_err(Error::Database)?;
-    Ok(result.id)
+    .map_err(Error::Database)
 }
 
-pub async fn create_match_with_bet_records(
+/// Opens a match session for `wallet_id`, started now with a server-chosen seed, and links bet
+/// `bet_id` to it. Nothing is stored if the bet cannot be linked.
+pub async fn start_match_session(
     pool: &PgPool,
-    match_record: MatchRecord,
-    bet_id: i64,
-) -> Result<(), Error> {
-    let match_id = create_match_record(pool, match_record).await?;
-    sqlx::query!(
-        "INSERT INTO bet_record (id, match_id, requester_address, receiver_address, bet_tier, bet_amount, dead_line, timestamp, status) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
-        bet_id,
-        match_id as i64,
-        "", // requester_address
-        "", // receiver_address
-        "", // bet_tier
-        0.0, // bet_amount
-        0, // dead_line
-        0, // timestamp
-        "" // status
+    wallet_id: &str,
+    session_id: &str,
+    seed: i64,
+    bet_id: Option<i64>,
+) -> Result<MatchRecord, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+    let match_record = sqlx::query_as!(
+        MatchRecord,
+        "INSERT INTO match_record (wallet_id, start_time, player_point, status, session_id, seed) VALUES ($1, $2, 0, $3, $4, $5) RETURNING id, wallet_id, start_time, end_time, play_data, player_point, status AS \"status: MatchStatus\", session_id, seed",
+        wallet_id,
+        chrono::Utc::now().timestamp(),
+        MatchStatus::OnMatch.to_string(),
+        session_id,
+        seed
     )
-    .execute(pool)
+    .fetch_one(&mut tx)
     .await
     .map_err(Error::Database)?;
+    if let Some(bet_id) = bet_id {
+        bet_record::link_match(&mut tx, bet_id, match_record.id, wallet_id).await?;
+    }
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(match_record)
+}
 
-    Ok(())
+pub async fn get_match_by_session_id(
+    pool: &PgPool,
+    session_id: &str,
+) -> Result<MatchRecord, Error> {
+    sqlx::query_as!(
+        MatchRecord,
+        "SELECT id, wallet_id, start_time, end_time, play_data, player_point, status AS \"status: MatchStatus\", session_id, seed FROM match_record WHERE session_id = $1",
+        session_id
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::RowNotFound => Error::NotFound("session"),
+        _ => Error::Database(e),
+    })
 }
 
-pub async fn get_latest_match_id(pool: &PgPool) -> Result<Option<i32>, Error> {
-    sqlx::query_scalar!("SELECT MAX(id) FROM match_record")
-        .fetch_one(pool)
-        .await
-        .map_err(Error::Database)
+/// Ends a session still on match and started after `started_after`, as `status`. Returns false
+/// if it was already ended or has expired, so each session accepts a single end submission.
+pub async fn end_match_session(
+    pool: &PgPool,
+    session_id: &str,
+    status: MatchStatus,
+    play_data: &str,
+    player_point: i32,
+    started_after: i64,
+) -> Result<bool, Error> {
+    let updated = sqlx::query!(
+        "UPDATE match_record SET end_time = $1, play_data = $2, player_point = $3, status = $4 WHERE session_id = $5 AND status = $6 AND start_time >= $7",
+        chrono::Utc::now().timestamp(),
+        play_data,
+        player_point,
+        status.to_string(),
+        session_id,
+        MatchStatus::OnMatch.to_string(),
+        started_after
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?
+    .rows_affected();
+    Ok(updated > 0)
 }
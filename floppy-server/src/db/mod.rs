@@ -1,3 +1,10 @@
+pub mod auth_nonce;
+pub mod bet_event;
 pub mod bet_record;
+pub mod gamble_permit;
+pub mod indexer_cursor;
+pub mod leaderboard;
 pub mod match_record;
 pub mod player;
+pub mod reward_ledger;
+pub mod vault_transaction;
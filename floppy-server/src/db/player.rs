@@ -1 +1,91 @@
+use crate::error::Error;
+use crate::models::{BetStatus, MatchStatus, Player, PlayerStats, PlayerStatus};
+use sqlx::PgPool;
 
+pub async fn get_player(pool: &PgPool, wallet_id: &str) -> Result<Player, Error> {
+    sqlx::query_as!(
+        Player,
+        "SELECT wallet_id, created_date, update_date, status AS \"status: PlayerStatus\" FROM player WHERE LOWER(wallet_id) = LOWER($1)",
+        wallet_id
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::RowNotFound => Error::NotFound("player"),
+        _ => Error::Database(e),
+    })
+}
+
+/// Creates the player of a wallet the first time it signs in.
+pub async fn ensure_player(pool: &PgPool, wallet_id: &str) -> Result<(), Error> {
+    let now = chrono::Utc::now().timestamp();
+    sqlx::query!(
+        "INSERT INTO player (wallet_id, created_date, update_date, status) VALUES ($1, $2, $2, $3) ON CONFLICT (wallet_id) DO NOTHING",
+        wallet_id,
+        now,
+        PlayerStatus::Online.to_string()
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+    Ok(())
+}
+
+/// Marks a player online and records the heartbeat time.
+pub async fn record_heartbeat(pool: &PgPool, wallet_id: &str) -> Result<Player, Error> {
+    let now = chrono::Utc::now().timestamp();
+    sqlx::query_as!(
+        Player,
+        "UPDATE player SET status = $2, update_date = $3 WHERE LOWER(wallet_id) = LOWER($1)
+        RETURNING wallet_id, created_date, update_date, status AS \"status: PlayerStatus\"",
+        wallet_id,
+        PlayerStatus::Online.to_string(),
+        now
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::RowNotFound => Error::NotFound("player"),
+        _ => Error::Database(e),
+    })
+}
+
+/// Marks offline the online players whose last heartbeat is older than `last_seen_before`.
+/// Returns the number of players marked offline.
+pub async fn mark_idle_players_offline(pool: &PgPool, last_seen_before: i64) -> Result<u64, Error> {
+    let now = chrono::Utc::now().timestamp();
+    let updated = sqlx::query!(
+        "UPDATE player SET status = $1, update_date = $2 WHERE status = $3 AND COALESCE(update_date, 0) < $4",
+        PlayerStatus::Offline.to_string(),
+        now,
+        PlayerStatus::Online.to_string(),
+        last_seen_before
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?
+    .rows_affected();
+    Ok(updated)
+}
+
+/// Aggregates the matches and bets of a wallet. Addresses are compared case-insensitively as
+/// clients and the indexer do not agree on checksumming.
+pub async fn get_player_stats(pool: &PgPool, wallet_id: &str) -> Result<PlayerStats, Error> {
+    sqlx::query_as!(
+        PlayerStats,
+        "SELECT
+            (SELECT COUNT(*) FROM match_record WHERE LOWER(wallet_id) = LOWER($1) AND status = $2) AS \"matches_played!\",
+            COUNT(*) FILTER (WHERE status = $3 AND win) AS \"bets_won!\",
+            COUNT(*) FILTER (WHERE status = $3 AND NOT win) AS \"bets_lost!\",
+            COALESCE(SUM(bet_amount) FILTER (WHERE status IN ($3, $4)), 0)::TEXT AS \"total_wagered!\",
+            COALESCE(SUM(reward) FILTER (WHERE status = $3 AND win), 0)::TEXT AS \"total_rewards!\"
+        FROM bet_record WHERE LOWER(requester_address) = LOWER($1)",
+        wallet_id,
+        MatchStatus::OffMatch.to_string(),
+        BetStatus::Resolved.to_string(),
+        BetStatus::Pending.to_string()
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(Error::Database)
+}
@@ -0,0 +1,133 @@
+use crate::error::Error;
+use crate::models::VaultPermitRecord;
+use sqlx::{Executor, PgPool, Postgres};
+
+/// Seconds after its deadline during which an unused permit stays reserved, in case a
+/// transaction mined just before the deadline is reorged and included again.
+const EXPIRY_GRACE: i64 = 600;
+
+/// Rewards credited to `wallet_id` minus the permits that were or may still be used.
+///
+/// Withdrawals are accounted through the permits themselves: a permit for a nonce below the
+/// current vault nonce `nonce` was possibly used and stays reserved for good, while a permit for
+/// the current nonce is only released once expired, when it can no longer be used. Only one
+/// permit per nonce can be used, so each nonce reserves its largest permit.
+async fn available_reward<'e, E>(
+    executor: E,
+    wallet_id: &str,
+    nonce: i64,
+    now: i64,
+) -> Result<String, Error>
+where
+    E: Executor<'e, Database = Postgres>,
+{
+    sqlx::query_scalar!(
+        "SELECT GREATEST(
+            COALESCE((SELECT SUM(amount) FROM reward_ledger WHERE LOWER(wallet_id) = LOWER($1)), 0)
+            - COALESCE((
+                SELECT SUM(reserved) FROM (
+                    SELECT MAX(amount) AS reserved FROM vault_permit
+                    WHERE LOWER(requester) = LOWER($1) AND (nonce < $2 OR deadline + $3 > $4)
+                    GROUP BY nonce
+                ) permits
+            ), 0),
+            0
+        )::TEXT AS \"available!\"",
+        wallet_id,
+        nonce,
+        EXPIRY_GRACE,
+        now
+    )
+    .fetch_one(executor)
+    .await
+    .map_err(Error::Database)
+}
+
+/// Stores an issued permit if the requester still has enough reward available for it. The
+/// permit is signed for the requester's current vault nonce, which tells which earlier permits
+/// were possibly used, so a reward can never be signed out twice.
+pub async fn reserve_vault_permit(
+    pool: &PgPool,
+    vault_permit: &VaultPermitRecord,
+) -> Result<bool, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+
+    // Serialize permit issuance per requester
+    sqlx::query!(
+        "SELECT pg_advisory_xact_lock(hashtext(LOWER($1)))",
+        vault_permit.requester
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    let now = chrono::Utc::now().timestamp();
+    let available =
+        available_reward(&mut tx, &vault_permit.requester, vault_permit.nonce, now).await?;
+    let sufficient = sqlx::query_scalar!(
+        "SELECT $1::TEXT::NUMERIC <= $2::TEXT::NUMERIC AS \"sufficient!\"",
+        vault_permit.amount,
+        available
+    )
+    .fetch_one(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+    if !sufficient {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        "INSERT INTO vault_permit (requester, recipient, nonce, amount, deadline, signature, created_at) VALUES ($1, $2, $3, $4::TEXT::NUMERIC, $5, $6, $7)",
+        vault_permit.requester,
+        vault_permit.recipient,
+        vault_permit.nonce,
+        vault_permit.amount,
+        vault_permit.deadline,
+        vault_permit.signature,
+        now
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_available_reward(pool: PgPool) {
+        let now = 1_700_000_000;
+        let expired = now - EXPIRY_GRACE - 1;
+        sqlx::query(
+            "INSERT INTO reward_ledger (wallet_id, amount, created_at) VALUES ('0xAb', 100, 0)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        for (nonce, amount, deadline) in [
+            // Used, its withdrawal may not be indexed yet
+            (0, 30, expired),
+            // Expired with its nonce unused
+            (1, 40, expired),
+            // Still usable, only one of them can be
+            (1, 20, now + 60),
+            (1, 25, now + 60),
+        ] {
+            sqlx::query("INSERT INTO vault_permit (requester, recipient, nonce, amount, deadline, signature, created_at) VALUES ('0xab', '0xab', $1, $2, $3, '0x', 0)")
+                .bind(nonce as i64)
+                .bind(amount as i64)
+                .bind(deadline)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(available_reward(&pool, "0xAB", 1, now).await.unwrap(), "45");
+        // Once nonce 1 is used, its largest permit stays reserved
+        assert_eq!(available_reward(&pool, "0xAB", 2, now).await.unwrap(), "30");
+    }
+}
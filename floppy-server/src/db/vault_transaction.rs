@@ -0,0 +1,43 @@
+use crate::error::Error;
+use crate::models::VaultTransaction;
+use sqlx::PgPool;
+
+/// Inserts a vault transaction, ignoring logs that were already indexed.
+pub async fn create_vault_transaction(
+    pool: &PgPool,
+    vault_transaction: VaultTransaction,
+) -> Result<bool, Error> {
+    let inserted = sqlx::query!(
+        "INSERT INTO vault_transaction (wallet_id, transaction_type, amount, shares, nonce, transaction_date, status, transaction_id, block_number, log_index, recipient) VALUES ($1, $2, $3::TEXT::NUMERIC, $4::TEXT::NUMERIC, $5, $6, $7, $8, $9, $10, $11) ON CONFLICT (transaction_id, log_index) DO NOTHING",
+        vault_transaction.wallet_id,
+        vault_transaction.transaction_type,
+        vault_transaction.amount,
+        vault_transaction.shares,
+        vault_transaction.nonce,
+        vault_transaction.transaction_date,
+        vault_transaction.status,
+        vault_transaction.transaction_id,
+        vault_transaction.block_number,
+        vault_transaction.log_index,
+        vault_transaction.recipient
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?
+    .rows_affected();
+    Ok(inserted > 0)
+}
+
+pub async fn get_vault_transactions_by_wallet(
+    pool: &PgPool,
+    wallet_id: &str,
+) -> Result<Vec<VaultTransaction>, Error> {
+    sqlx::query_as!(
+        VaultTransaction,
+        "SELECT id, wallet_id, transaction_type, amount::TEXT AS amount, shares::TEXT AS shares, nonce, transaction_date, status, transaction_id, block_number, log_index, recipient FROM vault_transaction WHERE LOWER(wallet_id) = LOWER($1) ORDER BY block_number DESC, log_index DESC",
+        wallet_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
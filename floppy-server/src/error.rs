@@ -1,12 +1,177 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::Serialize;
+use serde_json::Value;
 use thiserror::Error;
 use warp::reject::Reject;
 
+use crate::play_data::ReplayError;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("database error: {0}")]
     Database(#[from] sqlx::Error),
-    #[error("not found")]
-    NotFound,
+    /// The named resource does not exist.
+    #[error("{0} not found")]
+    NotFound(&'static str),
+    /// The request is malformed or has invalid parameters.
+    #[error("{0}")]
+    Validation(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    /// The authenticated wallet may not act on the resource.
+    #[error("{0}")]
+    Forbidden(String),
+    /// The resource is not in a state allowing the request.
+    #[error("{0}")]
+    Conflict(String),
+    /// The resource existed but can no longer be used.
+    #[error("{0}")]
+    Expired(String),
+    /// The request is well-formed but its content is rejected.
+    #[error("{0}")]
+    Unprocessable(String),
+    #[error(transparent)]
+    PlayData(#[from] ReplayError),
+    /// The chain RPC could not be reached or answered an error.
+    #[error("chain rpc error: {0}")]
+    Chain(String),
+    #[error("signing error: {0}")]
+    Signing(String),
+    /// Permits would be rejected by the contract, the configured key is not its signer.
+    #[error("permit signer does not match the contract signer")]
+    SignerMismatch,
+    #[error("{0}")]
+    Internal(String),
 }
 
 impl Reject for Error {}
+
+/// Body of every error response.
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    /// Stable machine-readable code, see [`Error::code`].
+    pub code: &'static str,
+    pub message: String,
+    pub details: Option<Value>,
+}
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Database(_) => "database_error",
+            Error::NotFound(_) => "not_found",
+            Error::Validation(_) => "validation_error",
+            Error::Unauthorized(_) => "unauthorized",
+            Error::Forbidden(_) => "forbidden",
+            Error::Conflict(_) => "conflict",
+            Error::Expired(_) => "expired",
+            Error::Unprocessable(_) => "unprocessable",
+            Error::PlayData(ReplayError::InvalidFormat(_))
+            | Error::PlayData(ReplayError::UnsupportedVersion(_))
+            | Error::PlayData(ReplayError::InvalidInputs(_)) => "invalid_play_data",
+            Error::PlayData(_) => "play_data_mismatch",
+            Error::Chain(_) => "chain_rpc_error",
+            Error::Signing(_) => "signing_error",
+            Error::SignerMismatch => "signer_mismatch",
+            Error::Internal(_) => "internal_error",
+        }
+    }
+
+    fn details(&self) -> Option<Value> {
+        match self {
+            Error::NotFound(resource) => Some(serde_json::json!({ "resource": resource })),
+            Error::PlayData(ReplayError::EndMismatch { claimed, replayed }) => {
+                Some(serde_json::json!({ "claimed_end": claimed, "replayed_end": replayed }))
+            }
+            Error::PlayData(ReplayError::ScoreMismatch { claimed, replayed }) => {
+                Some(serde_json::json!({ "claimed_points": claimed, "replayed_points": replayed }))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        let message = match self {
+            // Database errors can carry queries and values, they are only logged
+            Error::Database(_) => "database error".to_string(),
+            _ => self.to_string(),
+        };
+        ErrorBody {
+            code: self.code(),
+            message,
+            details: self.details(),
+        }
+    }
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Validation(_) => StatusCode::BAD_REQUEST,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::Expired(_) => StatusCode::GONE,
+            Error::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::PlayData(ReplayError::InvalidFormat(_))
+            | Error::PlayData(ReplayError::UnsupportedVersion(_))
+            | Error::PlayData(ReplayError::InvalidInputs(_)) => StatusCode::BAD_REQUEST,
+            Error::PlayData(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::Chain(_) => StatusCode::BAD_GATEWAY,
+            Error::SignerMismatch => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Database(_) | Error::Signing(_) | Error::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            eprintln!("Request failed: {}", self);
+        }
+        HttpResponse::build(status).json(self.body())
+    }
+}
+
+/// Turns extractor failures (bad JSON body, query or path) into [`Error::Validation`] responses.
+pub fn validation_error(
+    err: impl std::fmt::Display,
+    _: &actix_web::HttpRequest,
+) -> actix_web::Error {
+    Error::Validation(err.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::MessageBody;
+
+    #[test]
+    fn test_error_response() {
+        let response = Error::NotFound("bet").error_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = response.into_body().try_into_bytes().unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "code": "not_found",
+                "message": "bet not found",
+                "details": { "resource": "bet" },
+            })
+        );
+
+        let mismatch = Error::PlayData(ReplayError::ScoreMismatch {
+            claimed: 10,
+            replayed: 3,
+        });
+        assert_eq!(mismatch.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(mismatch.code(), "play_data_mismatch");
+
+        let database = Error::Database(sqlx::Error::PoolTimedOut).body();
+        assert_eq!(database.code, "database_error");
+        assert_eq!(database.message, "database error");
+    }
+}
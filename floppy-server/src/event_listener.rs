@@ -1,30 +1,46 @@
 use alloy::{
-    primitives::{address, utils::format_units, Address, U256},
+    primitives::{Address, TxHash, U256},
     providers::{Provider, ProviderBuilder, RootProvider},
-    rpc::types::{BlockNumberOrTag, Filter},
+    rpc::types::{BlockNumberOrTag, Filter, Header},
     sol,
-    sol_types::SolEvent,
+    sol_types::{SolEventInterface, SolInterface},
     transports::http::Http,
 };
 use alloy_transport_http::{reqwest::Url, Client};
-use eyre::Result;
+use eyre::{eyre, Result};
 use sqlx::PgPool;
+use std::sync::Arc;
 use tokio::time::{interval, Duration};
 
 use crate::{
-    db::{bet_record, match_record},
-    models::BetRecord,
+    bet_ingest,
+    config::Config,
+    db::{bet_event, bet_record, indexer_cursor, vault_transaction},
+    models::{BetEvent, BetStatus, BetTransition, VaultTransaction, VaultTransactionType},
+    signer::PermitSigner,
+    supervisor::Worker,
+    tier_config::TierConfigCache,
 };
 
+/// Name of the cursor row tracking the gamble and vault proxies.
+const CURSOR_NAME: &str = "floppy_contracts";
+/// Maximum number of blocks requested in a single `eth_getLogs` call.
+const MAX_BLOCK_RANGE: u64 = 500;
+/// Upper bound of the adaptive `eth_getLogs` range used by backfills.
+const MAX_BACKFILL_RANGE: u64 = 5000;
+/// Number of blocks of checkpoints kept around to find a common ancestor on reorg.
+const CHECKPOINT_HISTORY: i64 = 1024;
+
 pub struct EventListener {
     provider: RootProvider<Http<Client>>,
     db_pool: PgPool,
-}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct BetPlaced {
-    pub requester: Address,
-    pub bet_id: U256,
+    gamble_address: Address,
+    vault_address: Address,
+    confirmations: u64,
+    start_block: Option<u64>,
+    rpc_url: String,
+    permit_signer: Arc<PermitSigner>,
+    tier_config: Arc<TierConfigCache>,
 }
 
 sol! {
@@ -34,77 +50,527 @@ sol! {
     "abi/FloppyGamble.json"
 }
 
+sol! {
+    #[allow(missing_docs)]
+    #[sol(rpc)]
+    FloppyVault,
+    "abi/FloppyVault.json"
+}
+
+use FloppyGamble::{FloppyGambleCalls, FloppyGambleEvents};
+use FloppyVault::FloppyVaultEvents;
+
+pub(crate) type GambleContract =
+    FloppyGamble::FloppyGambleInstance<Http<Client>, RootProvider<Http<Client>>>;
+
+/// Position of a log on chain, identifying the change it produced.
+struct LogMeta {
+    tx_hash: TxHash,
+    log_index: u64,
+    block_number: u64,
+}
+
+impl LogMeta {
+    fn bet_event(&self, bet_id: U256, event_name: &str) -> Result<BetEvent> {
+        Ok(BetEvent {
+            bet_id: bet_id.to_string().parse()?,
+            event_name: event_name.to_string(),
+            tx_hash: self.tx_hash.to_string(),
+            log_index: self.log_index as i64,
+            block_number: self.block_number as i64,
+        })
+    }
+}
+
 impl EventListener {
-    pub fn new(rpc_url: String, db_pool: PgPool) -> Result<Self> {
-        let url = Url::parse(&rpc_url)?;
+    pub fn new(
+        config: &Config,
+        db_pool: PgPool,
+        permit_signer: Arc<PermitSigner>,
+        tier_config: Arc<TierConfigCache>,
+    ) -> Result<Self> {
+        let url = Url::parse(&config.rpc_url)?;
         let provider = ProviderBuilder::new().on_http(url.clone());
 
-        Ok(Self { provider, db_pool })
+        Ok(Self {
+            provider,
+            db_pool,
+            gamble_address: config.gamble.address,
+            vault_address: config.vault.address,
+            confirmations: config.confirmations,
+            start_block: config.start_block,
+            rpc_url: config.rpc_url.clone(),
+            permit_signer,
+            tier_config,
+        })
     }
 
-    pub async fn run(&self) -> Result<()> {
+    /// Polls confirmed blocks until shutdown, which waits for the batch in flight.
+    pub async fn run(&self, mut worker: Worker) -> Result<()> {
         println!("Running event listener");
-        let contract_address = address!("ec6be1d0c53489de129b2c13ac3edb393865c22f");
+        let mut interval = interval(Duration::from_secs(5));
+        let gamble_contract = self.gamble_contract();
 
-        let bet_placed_filter = Filter::new()
-            .address(contract_address)
-            .event("BetPlaced(address,uint256)");
+        while worker.tick(&mut interval).await {
+            // The cursor is persisted after every batch, so a failed poll is retried on the next tick
+            if let Err(e) = self.poll(&gamble_contract).await {
+                eprintln!("Error polling events: {}", e);
+            }
+        }
+        Ok(())
+    }
 
-        let mut interval = interval(Duration::from_secs(5));
-        let mut last_block = BlockNumberOrTag::Latest;
-        let gamble_contract = FloppyGamble::new(
-            address!("ec6be1d0c53489de129b2c13ac3edb393865c22f"),
-            self.provider.clone(),
-        );
+    /// Processes the next batch of confirmed blocks after the persisted cursor.
+    async fn poll(&self, gamble_contract: &GambleContract) -> Result<()> {
+        let head = self.provider.get_block_number().await?;
+        let safe_block = head.saturating_sub(self.confirmations);
 
-        loop {
-            interval.tick().await;
-
-            // Listen for BetPlaced events
-            let bet_filter = bet_placed_filter.clone().from_block(last_block);
-            match self.provider.get_logs(&bet_filter).await {
-                Ok(logs) => {
-                    for log in logs {
-                        // Extract requester address and bet ID from the log
-                        match log.topic0() {
-                            // Match the `BetPlaced(address,uint256)` event.
-                            Some(&FloppyGamble::BetPlaced::SIGNATURE_HASH) => {
-                                let FloppyGamble::BetPlaced { requester, betId } =
-                                    log.log_decode()?.inner.data;
-                                println!("New bet placed by: {}, bet ID: {}", requester, betId);
-                                let bet_info = gamble_contract.getBetInfoById(betId).call().await?;
-                                self.sync_bet(betId, bet_info._0).await?;
-                            }
-                            _ => (),
-                        }
+        let from_block = match indexer_cursor::get_cursor(&self.db_pool, CURSOR_NAME).await? {
+            Some(cursor) => {
+                let next_block = cursor.block_number as u64 + 1;
+                if next_block > safe_block {
+                    return Ok(());
+                }
+                let next_header = self.block_header(next_block).await?;
+                if next_header.parent_hash.to_string() != cursor.block_hash {
+                    self.handle_reorg(gamble_contract).await?;
+                    return Ok(());
+                }
+                next_block
+            }
+            None => self.start_block.unwrap_or(safe_block),
+        };
+        if from_block > safe_block {
+            return Ok(());
+        }
+        let to_block = safe_block.min(from_block + MAX_BLOCK_RANGE - 1);
+        self.process_range(gamble_contract, from_block, to_block)
+            .await
+    }
+
+    /// Rebuilds bets and vault history from every event between `from_block` and the confirmed head.
+    ///
+    /// Logs are requested in chunks that shrink whenever the RPC rejects a range as too large
+    /// and grow back after successful requests. The cursor is advanced after each chunk, so an
+    /// interrupted backfill can be resumed and the listener continues where it stopped.
+    pub async fn backfill(&self, from_block: u64) -> Result<()> {
+        let gamble_contract = self.gamble_contract();
+        let head = self.provider.get_block_number().await?;
+        let safe_block = head.saturating_sub(self.confirmations);
+        println!("Backfilling blocks {} to {}", from_block, safe_block);
+
+        let mut block = from_block;
+        let mut chunk_size = MAX_BACKFILL_RANGE;
+        while block <= safe_block {
+            let to_block = safe_block.min(block + chunk_size - 1);
+            match self.process_range(&gamble_contract, block, to_block).await {
+                Ok(()) => {
+                    println!("Backfilled blocks {} to {}", block, to_block);
+                    block = to_block + 1;
+                    chunk_size = (chunk_size * 2).min(MAX_BACKFILL_RANGE);
+                }
+                Err(e) if is_range_too_large(&e) && chunk_size > 1 => {
+                    chunk_size /= 2;
+                    eprintln!(
+                        "Block range too large, retrying with {} blocks: {}",
+                        chunk_size, e
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        println!("Backfill complete");
+        Ok(())
+    }
+
+    fn gamble_contract(&self) -> GambleContract {
+        FloppyGamble::new(self.gamble_address, self.provider.clone())
+    }
+
+    /// Applies every gamble and vault event between `from_block` and `to_block` and checkpoints `to_block`.
+    async fn process_range(
+        &self,
+        gamble_contract: &GambleContract,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<()> {
+        let vault_address = self.vault_address;
+        let filter = Filter::new()
+            .address(vec![*gamble_contract.address(), vault_address])
+            .from_block(from_block)
+            .to_block(to_block);
+
+        for log in self.provider.get_logs(&filter).await? {
+            let meta = LogMeta {
+                tx_hash: log
+                    .transaction_hash
+                    .ok_or_else(|| eyre!("log without transaction hash"))?,
+                log_index: log
+                    .log_index
+                    .ok_or_else(|| eyre!("log without log index"))?,
+                block_number: log
+                    .block_number
+                    .ok_or_else(|| eyre!("log without block number"))?,
+            };
+            // Skip logs the ABIs do not know about, e.g. proxy and ownership events
+            if log.address() == vault_address {
+                if let Ok(event) = FloppyVaultEvents::decode_log(&log.inner, true) {
+                    self.handle_vault_event(event.data, meta).await?;
+                }
+            } else if let Ok(event) = FloppyGambleEvents::decode_log(&log.inner, true) {
+                self.handle_event(gamble_contract, event.data, meta).await?;
+            }
+        }
+
+        let to_header = self.block_header(to_block).await?;
+        indexer_cursor::save_cursor(
+            &self.db_pool,
+            CURSOR_NAME,
+            to_block as i64,
+            &to_header.hash.to_string(),
+            CHECKPOINT_HISTORY,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Rewinds to the latest checkpoint still on the canonical chain and resets rows from orphaned blocks.
+    async fn handle_reorg(&self, gamble_contract: &GambleContract) -> Result<()> {
+        let checkpoints = indexer_cursor::get_checkpoints(&self.db_pool, CURSOR_NAME).await?;
+        for checkpoint in checkpoints {
+            let header = self.block_header(checkpoint.block_number as u64).await?;
+            if header.hash.to_string() == checkpoint.block_hash {
+                let bet_ids = indexer_cursor::rollback_to(
+                    &self.db_pool,
+                    CURSOR_NAME,
+                    checkpoint.block_number,
+                )
+                .await?;
+                eprintln!(
+                    "Reorg detected, rolled back to block {} and re-syncing {} bets",
+                    checkpoint.block_number,
+                    bet_ids.len()
+                );
+                for bet_id in bet_ids {
+                    let bet_id = U256::from(bet_id);
+                    let bet_info = gamble_contract.getBetInfoById(bet_id).call().await?._0;
+                    // Bets missing from the new chain read as zeroed structs, their row keeps its
+                    // details until the bet is placed again
+                    if BetStatus::from(bet_info.status) == BetStatus::Unknown {
+                        continue;
                     }
+                    bet_ingest::ingest_bet(&self.db_pool, bet_id, &bet_info, None).await?;
+                }
+                return Ok(());
+            }
+        }
+        Err(eyre!(
+            "reorg is deeper than the {} blocks of checkpoint history",
+            CHECKPOINT_HISTORY
+        ))
+    }
+
+    async fn block_header(&self, block_number: u64) -> Result<Header> {
+        self.provider
+            .get_block_by_number(BlockNumberOrTag::Number(block_number), false)
+            .await?
+            .map(|block| block.header)
+            .ok_or_else(|| eyre!("block {} not found", block_number))
+    }
+
+    /// Applies a decoded gamble event to the bet it refers to.
+    async fn handle_event(
+        &self,
+        gamble_contract: &GambleContract,
+        event: FloppyGambleEvents,
+        meta: LogMeta,
+    ) -> Result<()> {
+        match event {
+            FloppyGambleEvents::BetPlaced(FloppyGamble::BetPlaced { requester, betId }) => {
+                println!("New bet placed by: {}, bet ID: {}", requester, betId);
+                let bet_info = gamble_contract.getBetInfoById(betId).call().await?;
+                bet_ingest::ingest_bet(&self.db_pool, betId, &bet_info._0, Some(meta.block_number))
+                    .await?;
+                bet_event::record_event(&self.db_pool, &meta.bet_event(betId, "BetPlaced")?)
+                    .await?;
+            }
+            FloppyGambleEvents::BetCanceled(FloppyGamble::BetCanceled { requester, betId }) => {
+                println!("Bet canceled by: {}, bet ID: {}", requester, betId);
+                self.ensure_bet(gamble_contract, betId).await?;
+                bet_event::apply_transition(
+                    &self.db_pool,
+                    &meta.bet_event(betId, "BetCanceled")?,
+                    BetTransition::Canceled,
+                )
+                .await?;
+            }
+            FloppyGambleEvents::BetResolved(FloppyGamble::BetResolved { betId, win }) => {
+                println!("Bet resolved, bet ID: {}, win: {}", betId, win);
+                self.ensure_bet(gamble_contract, betId).await?;
+                // The event only carries the outcome, points and reward are read from the contract
+                let bet_info = gamble_contract.getBetInfoById(betId).call().await?._0;
+                bet_event::apply_transition(
+                    &self.db_pool,
+                    &meta.bet_event(betId, "BetResolved")?,
+                    BetTransition::Resolved {
+                        win,
+                        points: bet_info.points.to_string().parse()?,
+                        reward: bet_info.reward.to_string(),
+                    },
+                )
+                .await?;
+            }
+            FloppyGambleEvents::RewardClaimed(FloppyGamble::RewardClaimed { receiver, amount }) => {
+                println!("Reward of {} claimed by: {}", amount, receiver);
+                let Some(bet_id) = self.claimed_bet_id(&meta, receiver, amount).await? else {
+                    eprintln!("No bet found for reward claimed in tx {}", meta.tx_hash);
+                    return Ok(());
+                };
+                bet_event::apply_transition(
+                    &self.db_pool,
+                    &meta.bet_event(U256::from(bet_id), "RewardClaimed")?,
+                    BetTransition::Claimed,
+                )
+                .await?;
+            }
+            FloppyGambleEvents::SignerUpdated(FloppyGamble::SignerUpdated { signer }) => {
+                println!("Gamble signer updated to: {}", signer);
+                // Follow the current on-chain signer rather than the event, which may be historical
+                if let Err(e) = self
+                    .permit_signer
+                    .sync_with_contract(&self.rpc_url, self.gamble_address)
+                    .await
+                {
+                    eprintln!("Cannot rotate permit signer: {}", e);
                 }
-                Err(e) => eprintln!("Error fetching bet logs: {}", e),
             }
-            // Update last block
-            if let Ok(current_block) = self.provider.get_block_number().await {
-                last_block = BlockNumberOrTag::Number(current_block);
+            FloppyGambleEvents::PointsRangesUpdated(_)
+            | FloppyGambleEvents::RewardPercentagesUpdated(_)
+            | FloppyGambleEvents::MinBetAmountUpdated(_)
+            | FloppyGambleEvents::MaxBetAmountUpdated(_) => {
+                println!("Gamble tier configuration updated");
+                if let Err(e) = self.tier_config.refresh().await {
+                    eprintln!("Cannot refresh tier configuration: {}", e);
+                }
             }
+            // Other configuration events do not change any bet
+            _ => (),
         }
+        Ok(())
     }
 
-    async fn sync_bet(&self, bet_id: U256, bet_info: IFloppyGamble::BetInfo) -> Result<()> {
-        let bet_record = BetRecord {
-            id: bet_id.to_string().parse()?,
-            match_id: 0,
-            requester_address: bet_info.requester.to_string(),
-            receiver_address: bet_info.receiver.to_string(),
-            bet_tier: Some(bet_info.tier.into()),
-            bet_amount: format_units(bet_info.amount, "ether")?.parse::<f64>()?,
-            timestamp: bet_info.timestamp.to_string().parse()?,
-            status: Some(bet_info.status.into()),
-            dead_line: 0,
+    /// Records vault deposits, withdrawals, reward withdrawals and nonce changes per wallet.
+    async fn handle_vault_event(&self, event: FloppyVaultEvents, meta: LogMeta) -> Result<()> {
+        // `WithdrawReward` names the recipient, the reward is withdrawn by the permit submitter
+        let submitter = match &event {
+            FloppyVaultEvents::WithdrawReward(_) => Some(
+                self.provider
+                    .get_transaction_by_hash(meta.tx_hash)
+                    .await?
+                    .ok_or_else(|| eyre!("transaction {} not found", meta.tx_hash))?
+                    .from,
+            ),
+            _ => None,
+        };
+        let Some(VaultEventFields {
+            wallet,
+            transaction_type,
+            amount,
+            shares,
+            nonce,
+            recipient,
+        }) = vault_event_fields(event, submitter)
+        else {
+            return Ok(());
         };
-        if bet_record::is_bet_exists(&self.db_pool, bet_record.id).await? {
-            bet_record::update_bet_record(&self.db_pool, bet_record).await?;
-        } else {
-            bet_record::create_bet_record(&self.db_pool, bet_record).await?;
+        println!(
+            "Vault {:?} by: {}, tx: {}",
+            transaction_type, wallet, meta.tx_hash
+        );
+
+        let block = self.block_header(meta.block_number).await?;
+        let vault_transaction = VaultTransaction {
+            id: 0,
+            wallet_id: Some(wallet.to_string()),
+            transaction_type: Some(transaction_type as i32),
+            amount: amount.map(|amount| amount.to_string()),
+            shares: shares.map(|shares| shares.to_string()),
+            nonce: nonce.map(|nonce| nonce.to_string().parse()).transpose()?,
+            transaction_date: Some(block.timestamp as i64),
+            status: Some(1),
+            transaction_id: Some(meta.tx_hash.to_string()),
+            block_number: Some(meta.block_number as i64),
+            log_index: Some(meta.log_index as i64),
+            recipient: recipient.map(|recipient| recipient.to_string()),
+        };
+        vault_transaction::create_vault_transaction(&self.db_pool, vault_transaction).await?;
+        Ok(())
+    }
+
+    /// Makes sure the bet row exists before a transition is applied, e.g. for bets placed before the start block.
+    async fn ensure_bet(&self, gamble_contract: &GambleContract, bet_id: U256) -> Result<()> {
+        if !bet_record::is_bet_exists(&self.db_pool, bet_id.to_string().parse()?).await? {
+            let bet_info = gamble_contract.getBetInfoById(bet_id).call().await?;
+            bet_ingest::ingest_bet(&self.db_pool, bet_id, &bet_info._0, None).await?;
         }
         Ok(())
     }
+
+    /// `RewardClaimed` has no bet id, so it is taken from the calldata of the claiming transaction,
+    /// falling back to the unclaimed bet of the receiver with the same reward.
+    async fn claimed_bet_id(
+        &self,
+        meta: &LogMeta,
+        receiver: Address,
+        amount: U256,
+    ) -> Result<Option<i64>> {
+        if let Some(tx) = self.provider.get_transaction_by_hash(meta.tx_hash).await? {
+            match FloppyGambleCalls::abi_decode(&tx.input, true) {
+                Ok(FloppyGambleCalls::claimReward(call)) => {
+                    return Ok(Some(call.betId.to_string().parse()?))
+                }
+                Ok(FloppyGambleCalls::resolveBetAndClaimReward(call)) => {
+                    return Ok(Some(call.betId.to_string().parse()?))
+                }
+                _ => (),
+            }
+        }
+        Ok(bet_event::find_unclaimed_bet_id(
+            &self.db_pool,
+            &receiver.to_string(),
+            &amount.to_string(),
+        )
+        .await?)
+    }
+}
+
+/// Wallet history fields of a vault event.
+struct VaultEventFields {
+    wallet: Address,
+    transaction_type: VaultTransactionType,
+    amount: Option<U256>,
+    shares: Option<U256>,
+    nonce: Option<U256>,
+    recipient: Option<Address>,
+}
+
+/// Fields stored for `event`, or `None` for events that are not wallet history. A reward
+/// withdrawal belongs to `submitter`, the sender of its transaction.
+fn vault_event_fields(
+    event: FloppyVaultEvents,
+    submitter: Option<Address>,
+) -> Option<VaultEventFields> {
+    let fields = match event {
+        FloppyVaultEvents::Deposit(FloppyVault::Deposit {
+            owner,
+            tokenAmount,
+            shares,
+            ..
+        }) => VaultEventFields {
+            wallet: owner,
+            transaction_type: VaultTransactionType::Deposit,
+            amount: Some(tokenAmount),
+            shares: Some(shares),
+            nonce: None,
+            recipient: None,
+        },
+        FloppyVaultEvents::Withdraw(FloppyVault::Withdraw {
+            owner,
+            tokenAmount,
+            shares,
+            ..
+        }) => VaultEventFields {
+            wallet: owner,
+            transaction_type: VaultTransactionType::Withdraw,
+            amount: Some(tokenAmount),
+            shares: Some(shares),
+            nonce: None,
+            recipient: None,
+        },
+        // The event field is named `sender` but holds the recipient
+        FloppyVaultEvents::WithdrawReward(FloppyVault::WithdrawReward {
+            sender: recipient,
+            tokenAmount,
+        }) => VaultEventFields {
+            wallet: submitter.unwrap_or(recipient),
+            transaction_type: VaultTransactionType::WithdrawReward,
+            amount: Some(tokenAmount),
+            shares: None,
+            nonce: None,
+            recipient: Some(recipient),
+        },
+        FloppyVaultEvents::UserNonceIncreased(FloppyVault::UserNonceIncreased {
+            user,
+            newNonce,
+        }) => VaultEventFields {
+            wallet: user,
+            transaction_type: VaultTransactionType::NonceIncreased,
+            amount: None,
+            shares: None,
+            nonce: Some(newNonce),
+            recipient: None,
+        },
+        // Token transfers, roles and configuration are not wallet history
+        _ => return None,
+    };
+    Some(fields)
+}
+
+/// Whether an RPC error asks for a smaller `eth_getLogs` block range.
+fn is_range_too_large(error: &eyre::Report) -> bool {
+    let message = error.to_string().to_lowercase();
+    [
+        "range too large",
+        "range is too large",
+        "exceed maximum block range",
+        "more than 10000 results",
+        "limit exceeded",
+        "response size exceeded",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::{
+        primitives::{address, Log},
+        sol_types::SolEvent,
+    };
+
+    #[test]
+    fn test_is_range_too_large() {
+        assert!(is_range_too_large(&eyre!(
+            "server returned an error response: error code -32005: query returned more than 10000 results"
+        )));
+        assert!(is_range_too_large(&eyre!(
+            "server returned an error response: error code -32600: block range too large"
+        )));
+        assert!(!is_range_too_large(&eyre!("connection refused")));
+    }
+
+    #[test]
+    fn test_vault_event_fields() {
+        let submitter = address!("f39Fd6e51aad88F6F4ce6aB8827279cffFb92266");
+        let recipient = address!("193542e0C9746e8a428b2a4430545AFdb87d95E8");
+        let log = Log {
+            address: Address::ZERO,
+            data: FloppyVault::WithdrawReward {
+                sender: recipient,
+                tokenAmount: U256::from(5),
+            }
+            .encode_log_data(),
+        };
+        let event = FloppyVaultEvents::decode_log(&log, true).unwrap().data;
+
+        let fields = vault_event_fields(event, Some(submitter)).unwrap();
+        assert_eq!(fields.wallet, submitter);
+        assert_eq!(fields.recipient, Some(recipient));
+        assert_eq!(
+            fields.transaction_type,
+            VaultTransactionType::WithdrawReward
+        );
+        assert_eq!(fields.amount, Some(U256::from(5)));
+    }
 }
@@ -1,53 +1,180 @@
-use actix_web::{get, web, App, HttpServer, Responder};
-use alloy::transports::http::reqwest::Url;
-use serde_json::{json, Value};
-use sqlx::{postgres::PgPoolOptions, PgPool};
-use std::fs;
-use tokio::task;
+use actix_web::{middleware, web, App, HttpResponse, HttpServer};
+use sqlx::postgres::PgPoolOptions;
+use std::sync::Arc;
+use tokio::{signal, task, time::Duration};
 
+mod auth;
+mod bet_ingest;
 mod bets_syncer;
+mod config;
 mod db;
 mod error;
 mod event_listener;
 mod models;
+mod play_data;
+mod presence;
 mod router;
 mod signer;
 mod state;
+mod supervisor;
+mod tier_config;
+
+/// Time given to in-flight requests, then to worker batches, to complete on shutdown.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let config = config::Config::load().expect("Failed to load config");
+    config.validate().await.expect("Invalid config");
+
     let pool = PgPoolOptions::new()
         .max_connections(5)
-        .connect(&database_url)
+        .connect(&config.database_url)
         .await
         .expect("Failed to create pool");
+    // Migrations are embedded at build time from ./migrations, the same schema the queries
+    // are checked against
+    sqlx::migrate!()
+        .run(&pool)
+        .await
+        .expect("Failed to run migrations");
+
+    let permit_signer = Arc::new(
+        signer::PermitSigner::load(&config.signers)
+            .await
+            .expect("Failed to load signer"),
+    );
+    if let Err(e) = permit_signer
+        .sync_with_contract(&config.rpc_url, config.gamble.address)
+        .await
+    {
+        eprintln!("Cannot select the on-chain permit signer: {}", e);
+    }
+    println!("Signing permits as {}", permit_signer.address());
 
+    let tier_config = Arc::new(
+        tier_config::TierConfigCache::new(&config).expect("Failed to create TierConfigCache"),
+    );
     let event_listener = event_listener::EventListener::new(
-        std::env::var("RPC_URL").expect("RPC_URL must be set"),
+        &config,
         pool.clone(),
+        permit_signer.clone(),
+        tier_config.clone(),
     )
-    .expect("Failed to create BetsSyncer");
+    .expect("Failed to create EventListener");
 
-    task::spawn(async move {
-        if let Err(e) = event_listener.run().await {
-            eprintln!("Error running event listener: {}", e);
-        }
+    // `floppy-server backfill [from_block]` rebuilds bet_record from the chain and exits
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("backfill") {
+        let from_block = args
+            .next()
+            .map(|v| v.parse().expect("from block must be a number"))
+            .unwrap_or_else(|| {
+                config
+                    .gamble
+                    .deployment_block
+                    .min(config.vault.deployment_block)
+            });
+        event_listener
+            .backfill(from_block)
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        return Ok(());
+    }
+
+    let authenticator =
+        Arc::new(auth::Authenticator::new(&config).expect("Failed to create Authenticator"));
+    let signer_health = Arc::new(
+        signer::SignerHealth::new(&config, permit_signer.clone())
+            .expect("Failed to create SignerHealth"),
+    );
+    // Workers are restarted when they fail, and reported stuck after the given time without
+    // starting an iteration
+    let supervisor = Arc::new(supervisor::Supervisor::default());
+    let health_check = signer_health.clone();
+    supervisor.spawn("signer_health", Duration::from_secs(600), move |worker| {
+        let health_check = health_check.clone();
+        async move { health_check.run(worker).await }
+    });
+
+    let presence_tracker = Arc::new(presence::PresenceTracker::new(pool.clone()));
+    supervisor.spawn("presence", Duration::from_secs(120), move |worker| {
+        let presence_tracker = presence_tracker.clone();
+        async move { presence_tracker.run(worker).await }
+    });
+
+    let bets_syncer = Arc::new(
+        bets_syncer::BetsSyncer::new(&config, pool.clone()).expect("Failed to create BetsSyncer"),
+    );
+    let reconciliation = bets_syncer.clone();
+    supervisor.spawn("bets_syncer", Duration::from_secs(600), move |worker| {
+        let reconciliation = reconciliation.clone();
+        async move { reconciliation.run(worker).await }
+    });
+
+    let event_listener = Arc::new(event_listener);
+    supervisor.spawn("event_listener", Duration::from_secs(300), move |worker| {
+        let event_listener = event_listener.clone();
+        async move { event_listener.run(worker).await }
     });
 
+    let bind_address = config.bind_address.clone();
+    let workers = supervisor.clone();
     let server = HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(state::AppState {
-                db_pool: pool.clone(),
-                rpc_url: std::env::var("RPC_URL").expect("RPC_URL must be set"),
-            }))
+            .app_data(web::Data::new(state::AppState::new(
+                pool.clone(),
+                config.clone(),
+                permit_signer.clone(),
+                signer_health.clone(),
+                tier_config.clone(),
+                authenticator.clone(),
+                bets_syncer.clone(),
+                workers.clone(),
+            )))
+            .app_data(web::JsonConfig::default().error_handler(error::validation_error))
+            .app_data(web::QueryConfig::default().error_handler(error::validation_error))
+            .app_data(web::PathConfig::default().error_handler(error::validation_error))
+            .wrap(middleware::from_fn(auth::authenticate))
             .service(router::match_record::match_record_scope()) // Ensure this line is present
             .service(router::bet_record::bet_record_scope())
             .service(router::signer::signer_scope())
+            .service(router::vault_transaction::vault_transaction_scope())
+            .service(router::health::health_scope())
+            .service(router::auth::auth_scope())
+            .service(router::player::player_scope())
+            .service(router::leaderboard::leaderboard_scope())
+            .default_service(web::to(|| async {
+                Err::<HttpResponse, _>(error::Error::NotFound("route"))
+            }))
     })
-    .bind(("127.0.0.1", 8080))?
+    // Signals are handled below so the workers stop along with the server
+    .disable_signals()
+    .shutdown_timeout(SHUTDOWN_GRACE.as_secs())
+    .bind(bind_address)?
     .run();
 
-    server.await
+    let server_handle = server.handle();
+    let stopping = supervisor.clone();
+    task::spawn(async move {
+        wait_for_signal().await;
+        println!("Shutting down, finishing in-flight requests and batches");
+        stopping.request_shutdown();
+        server_handle.stop(true).await;
+    });
+
+    server.await?;
+    supervisor.shutdown(SHUTDOWN_GRACE).await;
+    Ok(())
+}
+
+/// Resolves on SIGTERM or Ctrl-C.
+async fn wait_for_signal() {
+    let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())
+        .expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = terminate.recv() => (),
+        _ = signal::ctrl_c() => (),
+    }
 }
@@ -1,12 +1,10 @@
 use std::fmt;
 
-use alloy::primitives::{I256, U256, U8};
-use chrono::{NaiveDate, NaiveDateTime};
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
 // Enum types
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
 pub enum PlayerStatus {
     Online,
     Offline,
@@ -16,9 +14,11 @@ pub enum PlayerStatus {
 pub enum MatchStatus {
     OnMatch,
     OffMatch,
+    /// Ended with play data that failed verification, the match scores nothing.
+    Rejected,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, sqlx::Type)]
 pub enum BetStatus {
     Unknown,
     Pending,
@@ -26,7 +26,7 @@ pub enum BetStatus {
     Canceled,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, sqlx::Type)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, sqlx::Type)]
 pub enum BetTier {
     Unknown,
     Bronze,
@@ -35,54 +35,211 @@ pub enum BetTier {
     Diamond,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum GameResult {
-    Win = 1,
-    Lose = 2,
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum VaultTransactionType {
+    Deposit = 1,
+    Withdraw = 2,
+    WithdrawReward = 3,
+    NonceIncreased = 4,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct Player {
-    wallet_id: String,
-    balance: U256,
-    created_date: NaiveDateTime,
-    update_date: NaiveDateTime,
-    status: PlayerStatus,
+    pub wallet_id: String,
+    pub created_date: Option<i64>,
+    /// Last heartbeat or profile change.
+    pub update_date: Option<i64>,
+    pub status: Option<PlayerStatus>,
+}
+
+/// Activity of a player, derived from their matches and bets.
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct PlayerStats {
+    pub matches_played: i64,
+    pub bets_won: i64,
+    pub bets_lost: i64,
+    /// Sum of placed, non-canceled bet amounts, in wei.
+    pub total_wagered: String,
+    /// Sum of rewards of won bets, in wei.
+    pub total_rewards: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct VaultTransaction {
-    pub id: U256,
-    pub wallet_id: String,
-    pub transaction_type: U256,
-    pub amount: U256,
-    pub transaction_date: NaiveDateTime,
-    pub status: U8,
-    pub transaction_id: String,
+    pub id: i32,
+    pub wallet_id: Option<String>,
+    pub transaction_type: Option<i32>,
+    pub amount: Option<String>,
+    pub shares: Option<String>,
+    pub nonce: Option<i64>,
+    pub transaction_date: Option<i64>,
+    pub status: Option<i32>,
+    pub transaction_id: Option<String>,
+    pub block_number: Option<i64>,
+    pub log_index: Option<i64>,
+    /// Receiver of the tokens of a reward withdrawal, which may differ from the wallet.
+    pub recipient: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct VaultPermitRecord {
+    pub requester: String,
+    pub recipient: String,
+    pub nonce: i64,
+    pub amount: String,
+    pub deadline: i64,
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct GamblePermitRecord {
+    pub bet_id: i64,
+    pub match_id: i64,
+    pub requester: String,
+    pub receiver: String,
+    pub points: i64,
+    pub bet_amount: String,
+    pub deadline: i64,
+    pub signature: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct MatchRecord {
-    pub id: i32,
+    pub id: i64,
     pub wallet_id: Option<String>,
     pub start_time: Option<i64>,
     pub end_time: Option<i64>,
     pub play_data: Option<String>,
     pub player_point: Option<i32>,
     pub status: Option<MatchStatus>,
+    /// Set for matches played through a server-issued session.
+    pub session_id: Option<String>,
+    pub seed: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct BetRecord {
     pub id: i64,
-    pub match_id: i64,
+    /// Match the bet was played in, once linked.
+    pub match_id: Option<i64>,
     pub requester_address: String,
     pub receiver_address: String,
     pub bet_tier: Option<BetTier>,
-    pub bet_amount: f64,
+    /// In wei.
+    pub bet_amount: String,
     pub dead_line: i64,
     pub timestamp: i64,
     pub status: Option<BetStatus>,
+    pub points: Option<i64>,
+    pub reward: Option<String>,
+    pub win: Option<bool>,
+    pub claimed: Option<bool>,
+}
+
+/// Best ended match of a wallet within a leaderboard. Equal points share a rank.
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct LeaderboardEntry {
+    pub rank: i64,
+    pub wallet_id: String,
+    pub points: i32,
+    pub match_id: i64,
+    pub achieved_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    Id,
+    /// Start time of a match, placement time of a bet.
+    Time,
+    Points,
+    /// Bet amount, bets only.
+    Amount,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Position after the last record of a page: its sort value and id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordCursor {
+    pub sort_value: String,
+    pub id: i64,
+}
+
+/// Filters, sort and position shared by the match and bet listings. Statuses and tiers are
+/// stored as text.
+#[derive(Debug, Clone)]
+pub struct RecordFilter {
+    /// Player of a match, requester of a bet.
+    pub wallet: Option<String>,
+    pub status: Option<String>,
+    pub tier: Option<String>,
+    pub match_id: Option<i64>,
+    /// Inclusive lower bound of the record time.
+    pub from: Option<i64>,
+    /// Exclusive upper bound of the record time.
+    pub to: Option<i64>,
+    pub sort: SortField,
+    pub order: SortOrder,
+    pub cursor: Option<RecordCursor>,
+}
+
+impl RecordFilter {
+    /// Sign applied to sort keys so a single ascending keyset query serves both orders.
+    pub fn direction(&self) -> i32 {
+        match self.order {
+            SortOrder::Asc => 1,
+            SortOrder::Desc => -1,
+        }
+    }
+
+    pub fn sort_name(&self) -> &'static str {
+        match self.sort {
+            SortField::Id => "id",
+            SortField::Time => "time",
+            SortField::Points => "points",
+            SortField::Amount => "amount",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct BetEvent {
+    pub bet_id: i64,
+    pub event_name: String,
+    pub tx_hash: String,
+    pub log_index: i64,
+    pub block_number: i64,
+}
+
+/// State change applied to a bet row by a gamble event.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BetTransition {
+    Canceled,
+    Resolved {
+        win: bool,
+        points: i64,
+        reward: String,
+    },
+    Claimed,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct IndexerCursor {
+    pub name: String,
+    pub block_number: i64,
+    pub block_hash: String,
+}
+
+impl fmt::Display for PlayerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
 }
 
 impl fmt::Display for MatchStatus {
@@ -116,6 +273,18 @@ impl From<u8> for BetTier {
     }
 }
 
+impl BetStatus {
+    /// Position in the life of a bet, Resolved and Canceled are both final. Mirrors
+    /// `bet_status_rank` in SQL.
+    pub fn rank(self) -> u8 {
+        match self {
+            BetStatus::Unknown => 0,
+            BetStatus::Pending => 1,
+            BetStatus::Resolved | BetStatus::Canceled => 2,
+        }
+    }
+}
+
 impl From<u8> for BetStatus {
     fn from(value: u8) -> Self {
         match value {
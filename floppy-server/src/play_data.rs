@@ -0,0 +1,330 @@
+//! Structured match play data and the deterministic replay that recomputes its score.
+//!
+//! A run is fully described by the seed that lays out the pipes and the frames on which the
+//! player flapped. The server replays it with the same integer physics as the client, so the
+//! points a permit is signed for are the points the inputs actually produce.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Current `PlayData::version`.
+pub const PLAY_DATA_VERSION: u32 = 1;
+pub const FRAMES_PER_SECOND: u32 = 60;
+/// Longest run replayed, 15 minutes.
+pub const MAX_FRAMES: u32 = FRAMES_PER_SECOND * 60 * 15;
+
+/// Positions are in thousandths of a pixel so the physics stay integral.
+const SCALE: i64 = 1000;
+const WORLD_HEIGHT: i64 = 512 * SCALE;
+const BIRD_X: i64 = 80;
+const BIRD_RADIUS: i64 = 12;
+const BIRD_START_Y: i64 = 256 * SCALE;
+const GRAVITY: i64 = 400;
+const FLAP_VELOCITY: i64 = -7_000;
+const MAX_FALL_SPEED: i64 = 10_000;
+const PIPE_SPEED: i64 = 2;
+const PIPE_WIDTH: i64 = 52;
+const PIPE_GAP: i64 = 130;
+const PIPE_SPACING: i64 = 200;
+const FIRST_PIPE_X: i64 = 400;
+/// Minimum distance between a gap and the top or bottom of the world.
+const PIPE_MARGIN: i64 = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayData {
+    pub version: u32,
+    /// Seed of the pipe layout.
+    pub seed: u64,
+    /// Frames on which the player flapped, strictly increasing.
+    pub flaps: Vec<u32>,
+    /// Number of frames played, the last one being the crash.
+    pub frames: u32,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ReplayError {
+    #[error("invalid play data: {0}")]
+    InvalidFormat(String),
+    #[error("unsupported play data version {0}")]
+    UnsupportedVersion(u32),
+    #[error("invalid inputs: {0}")]
+    InvalidInputs(String),
+    #[error("run ends at frame {claimed} but the replay crashes at frame {replayed:?}")]
+    EndMismatch { claimed: u32, replayed: Option<u32> },
+    #[error("claimed {claimed} points but the replay scores {replayed}")]
+    ScoreMismatch { claimed: i64, replayed: u32 },
+}
+
+/// SplitMix64, small and identical on every platform.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Frame-by-frame state of a run.
+pub struct Simulation {
+    rng: Rng,
+    /// Gap centers of the pipes generated so far, in pixels.
+    gaps: Vec<i64>,
+    frame: u32,
+    y: i64,
+    velocity: i64,
+    score: u32,
+}
+
+impl Simulation {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng(seed),
+            gaps: Vec::new(),
+            frame: 0,
+            y: BIRD_START_Y,
+            velocity: 0,
+            score: 0,
+        }
+    }
+
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+
+    fn gap_center(&mut self, pipe: usize) -> i64 {
+        let low = PIPE_GAP / 2 + PIPE_MARGIN;
+        let high = WORLD_HEIGHT / SCALE - PIPE_GAP / 2 - PIPE_MARGIN;
+        while self.gaps.len() <= pipe {
+            let center = low + (self.rng.next() % (high - low + 1) as u64) as i64;
+            self.gaps.push(center);
+        }
+        self.gaps[pipe]
+    }
+
+    /// Left edge of `pipe` after the current frame, in pixels.
+    fn pipe_x(&self, pipe: usize) -> i64 {
+        FIRST_PIPE_X + pipe as i64 * PIPE_SPACING - PIPE_SPEED * (self.frame as i64 + 1)
+    }
+
+    /// Advances one frame. Returns false if the bird crashed on this frame.
+    pub fn step(&mut self, flap: bool) -> bool {
+        if flap {
+            self.velocity = FLAP_VELOCITY;
+        }
+        self.velocity = (self.velocity + GRAVITY).min(MAX_FALL_SPEED);
+        self.y += self.velocity;
+
+        let radius = BIRD_RADIUS * SCALE;
+        let mut alive = self.y - radius >= 0 && self.y + radius <= WORLD_HEIGHT;
+
+        // Only the pipes near the bird can be passed or hit on this frame
+        let traveled = PIPE_SPEED * (self.frame as i64 + 1);
+        let first = ((traveled + BIRD_X - BIRD_RADIUS - FIRST_PIPE_X - PIPE_WIDTH)
+            .div_euclid(PIPE_SPACING))
+        .max(0) as usize;
+        for pipe in first.saturating_sub(1)..=first + 1 {
+            let x = self.pipe_x(pipe);
+            // A pipe scores on the frame its right edge passes the bird
+            if x + PIPE_WIDTH < BIRD_X && x + PIPE_WIDTH + PIPE_SPEED >= BIRD_X {
+                self.score += 1;
+            }
+            if x < BIRD_X + BIRD_RADIUS && x + PIPE_WIDTH > BIRD_X - BIRD_RADIUS {
+                let center = self.gap_center(pipe);
+                let top = (center - PIPE_GAP / 2) * SCALE;
+                let bottom = (center + PIPE_GAP / 2) * SCALE;
+                if self.y - radius < top || self.y + radius > bottom {
+                    alive = false;
+                }
+            }
+        }
+
+        self.frame += 1;
+        alive
+    }
+
+    /// Gap center of the next pipe the bird has to fly through, in pixels.
+    #[cfg(test)]
+    fn next_gap_center(&mut self) -> i64 {
+        let mut pipe = 0;
+        while self.pipe_x(pipe) + PIPE_WIDTH < BIRD_X - BIRD_RADIUS {
+            pipe += 1;
+        }
+        self.gap_center(pipe)
+    }
+}
+
+/// Result of replaying a run.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Replay {
+    pub score: u32,
+    /// Index of the frame the bird crashed on, if it did within the replayed frames.
+    pub crash_frame: Option<u32>,
+}
+
+pub fn replay(play_data: &PlayData) -> Result<Replay, ReplayError> {
+    if play_data.version != PLAY_DATA_VERSION {
+        return Err(ReplayError::UnsupportedVersion(play_data.version));
+    }
+    if play_data.frames == 0 || play_data.frames > MAX_FRAMES {
+        return Err(ReplayError::InvalidInputs(format!(
+            "frames must be between 1 and {}",
+            MAX_FRAMES
+        )));
+    }
+    if play_data.flaps.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(ReplayError::InvalidInputs(
+            "flaps must be strictly increasing".to_string(),
+        ));
+    }
+    if play_data
+        .flaps
+        .last()
+        .is_some_and(|frame| *frame >= play_data.frames)
+    {
+        return Err(ReplayError::InvalidInputs(
+            "flap after the end of the run".to_string(),
+        ));
+    }
+
+    let mut simulation = Simulation::new(play_data.seed);
+    let mut flaps = play_data.flaps.iter().peekable();
+    for frame in 0..play_data.frames {
+        let flap = flaps.next_if_eq(&&frame).is_some();
+        if !simulation.step(flap) {
+            return Ok(Replay {
+                score: simulation.score(),
+                crash_frame: Some(frame),
+            });
+        }
+    }
+    Ok(Replay {
+        score: simulation.score(),
+        crash_frame: None,
+    })
+}
+
+pub fn parse(play_data: Option<&str>) -> Result<PlayData, ReplayError> {
+    serde_json::from_str(
+        play_data.ok_or_else(|| ReplayError::InvalidFormat("play data is missing".to_string()))?,
+    )
+    .map_err(|e| ReplayError::InvalidFormat(e.to_string()))
+}
+
+/// Checks that the run ends on a crash at its last frame and scores `player_point`.
+/// Returns the replayed score.
+pub fn verify_run(play_data: &PlayData, player_point: Option<i32>) -> Result<u32, ReplayError> {
+    let replay = replay(play_data)?;
+    if replay.crash_frame != Some(play_data.frames - 1) {
+        return Err(ReplayError::EndMismatch {
+            claimed: play_data.frames - 1,
+            replayed: replay.crash_frame,
+        });
+    }
+    let claimed = player_point.unwrap_or(0) as i64;
+    if claimed != replay.score as i64 {
+        return Err(ReplayError::ScoreMismatch {
+            claimed,
+            replayed: replay.score,
+        });
+    }
+    Ok(replay.score)
+}
+
+/// Parses and verifies the play data submitted with a match.
+pub fn verify(play_data: Option<&str>, player_point: Option<i32>) -> Result<u32, ReplayError> {
+    verify_run(&parse(play_data)?, player_point)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A run without flaps, crashing before the first pipe with 0 points.
+    pub(crate) fn crashed_run(seed: u64) -> PlayData {
+        let mut play_data = PlayData {
+            version: PLAY_DATA_VERSION,
+            seed,
+            flaps: vec![],
+            frames: MAX_FRAMES,
+        };
+        play_data.frames = replay(&play_data).unwrap().crash_frame.unwrap() + 1;
+        play_data
+    }
+
+    /// Plays a run with a simple autopilot that flaps whenever it sinks below the next gap.
+    fn autopilot(seed: u64, max_frames: u32) -> PlayData {
+        let mut simulation = Simulation::new(seed);
+        let mut flaps = Vec::new();
+        let mut frame = 0;
+        while frame < max_frames {
+            let target = (simulation.next_gap_center() + PIPE_GAP / 4) * SCALE;
+            let flap = simulation.y > target && simulation.velocity > 0;
+            if flap {
+                flaps.push(frame);
+            }
+            frame += 1;
+            if !simulation.step(flap) {
+                break;
+            }
+        }
+        PlayData {
+            version: PLAY_DATA_VERSION,
+            seed,
+            flaps,
+            frames: frame,
+        }
+    }
+
+    #[test]
+    fn test_replay_is_deterministic() {
+        let play_data = autopilot(42, 3000);
+        let first = replay(&play_data).unwrap();
+        assert_eq!(first, replay(&play_data).unwrap());
+        assert!(first.score > 0);
+    }
+
+    #[test]
+    fn test_verify_crashed_run() {
+        // Without flapping the bird falls to the ground without scoring
+        let json = serde_json::to_string(&crashed_run(7)).unwrap();
+
+        assert_eq!(verify(Some(&json), Some(0)), Ok(0));
+        assert_eq!(
+            verify(Some(&json), Some(120)),
+            Err(ReplayError::ScoreMismatch {
+                claimed: 120,
+                replayed: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_verify_rejects_invalid_runs() {
+        let mut play_data = autopilot(42, 600);
+        play_data.frames += 10;
+        let json = serde_json::to_string(&play_data).unwrap();
+        assert!(matches!(
+            verify(Some(&json), Some(0)),
+            Err(ReplayError::EndMismatch { .. })
+        ));
+
+        play_data.flaps = vec![5, 5];
+        assert!(matches!(
+            replay(&play_data),
+            Err(ReplayError::InvalidInputs(_))
+        ));
+
+        assert!(matches!(
+            verify(Some("not json"), Some(0)),
+            Err(ReplayError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            verify(None, Some(0)),
+            Err(ReplayError::InvalidFormat(_))
+        ));
+    }
+}
@@ -0,0 +1,34 @@
+//! Player presence: heartbeats keep a player `Online`, and players whose heartbeats stop are
+//! swept back to `Offline`.
+
+use sqlx::PgPool;
+use tokio::time::{interval, Duration};
+
+use crate::{db::player, supervisor::Worker};
+
+/// Seconds without a heartbeat after which a player is considered offline.
+pub const HEARTBEAT_TIMEOUT: i64 = 90;
+/// Delay between two sweeps of idle players.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+pub struct PresenceTracker {
+    db_pool: PgPool,
+}
+
+impl PresenceTracker {
+    pub fn new(db_pool: PgPool) -> Self {
+        Self { db_pool }
+    }
+
+    pub async fn run(&self, mut worker: Worker) -> eyre::Result<()> {
+        let mut interval = interval(SWEEP_INTERVAL);
+        while worker.tick(&mut interval).await {
+            let last_seen_before = chrono::Utc::now().timestamp() - HEARTBEAT_TIMEOUT;
+            if let Err(e) = player::mark_idle_players_offline(&self.db_pool, last_seen_before).await
+            {
+                eprintln!("Cannot mark idle players offline: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use crate::auth::AuthenticatedWallet;
+use crate::db::{auth_nonce, player};
+use crate::error::Error;
+use crate::state::AppState;
+use actix_web::{get, post, web, HttpResponse, Scope};
+use alloy::{hex, primitives::Address};
+use serde::{Deserialize, Serialize};
+
+// Define a scope for auth routes
+pub fn auth_scope() -> Scope {
+    web::scope("/auth")
+        .service(get_nonce)
+        .service(login)
+        .service(get_session)
+}
+
+#[derive(Deserialize, Serialize)]
+struct NonceResponse {
+    nonce: String,
+    expires_at: i64,
+}
+
+#[derive(Deserialize, Serialize)]
+struct LoginData {
+    /// EIP-4361 message, signed as is with `personal_sign`.
+    message: String,
+    signature: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SessionResponse {
+    token: String,
+    wallet: Address,
+    expires_at: i64,
+}
+
+/// Issues a single-use nonce to embed in a sign-in message.
+#[get("/nonce")]
+async fn get_nonce(data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let nonce = hex::encode(rand::random::<[u8; 16]>());
+    let expires_at = chrono::Utc::now().timestamp() + data.auth.nonce_ttl();
+    auth_nonce::create_nonce(&data.db_pool, &nonce, expires_at).await?;
+    Ok(HttpResponse::Ok().json(NonceResponse { nonce, expires_at }))
+}
+
+/// Verifies a signed sign-in message and returns a session token for its wallet, creating its
+/// player on the first sign-in.
+#[post("/login")]
+async fn login(
+    data: web::Data<AppState>,
+    login_data: web::Json<LoginData>,
+) -> Result<HttpResponse, Error> {
+    let login_data = login_data.into_inner();
+    let message = data
+        .auth
+        .verify_message(&login_data.message, &login_data.signature)
+        .map_err(|e| Error::Unauthorized(e.to_string()))?;
+    // Consumed last so a rejected message does not burn the nonce
+    if !auth_nonce::consume_nonce(&data.db_pool, &message.nonce).await? {
+        return Err(Error::Unauthorized("unknown or expired nonce".to_string()));
+    }
+    player::ensure_player(&data.db_pool, &message.address.to_string()).await?;
+
+    let (token, expires_at) = data
+        .auth
+        .issue_token(message.address)
+        .map_err(|e| Error::Internal(e.to_string()))?;
+    Ok(HttpResponse::Ok().json(SessionResponse {
+        token,
+        wallet: message.address,
+        expires_at,
+    }))
+}
+
+/// Returns the wallet the session token belongs to.
+#[get("/session")]
+async fn get_session(wallet: AuthenticatedWallet) -> HttpResponse {
+    HttpResponse::Ok().json(wallet.0)
+}
@@ -1,42 +1,118 @@
-use crate::db::bet_record;
-use crate::models::BetRecord;
+use crate::db::{bet_record, match_record};
+use crate::error::Error;
+use crate::models::{BetStatus, RecordCursor, SortField};
+use crate::router::listing::{page, ListQuery};
 use crate::state::AppState;
-use actix_web::{get, post, web, HttpResponse, Responder, Scope};
+use actix_web::{get, web, HttpResponse, Scope};
+use alloy::primitives::U256;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 // Define a scope for match_record routes
 pub fn bet_record_scope() -> Scope {
     web::scope("/bet_record")
-        // .service(get_all_bet_records)
+        .service(get_all_bet_records)
         .service(get_bet_record_by_id)
-        .service(create_bet_record)
+        .service(get_bet_preview)
 }
 
-// #[get("")]
-// async fn get_all_bet_records(data: web::Data<AppState>) -> impl Responder {
-//     match bet_record::get_all_bet_records(&data.db_pool).await {
-//         Ok(records) => HttpResponse::Ok().json(records),
-//         Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
-//     }
-// }
+/// Lists bets a page at a time, see [`ListQuery`] for the parameters.
+#[get("")]
+async fn get_all_bet_records(
+    query: web::Query<ListQuery<BetStatus>>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let (filter, limit) = query.into_inner().into_filter(&[
+        SortField::Id,
+        SortField::Time,
+        SortField::Points,
+        SortField::Amount,
+    ])?;
+    let total = bet_record::count_bet_records(&data.db_pool, &filter).await?;
+    let records = bet_record::list_bet_records(&data.db_pool, &filter, limit + 1).await?;
+    Ok(
+        HttpResponse::Ok().json(page(records, limit, total, &filter, |record| {
+            RecordCursor {
+                sort_value: match filter.sort {
+                    SortField::Time => record.timestamp.to_string(),
+                    SortField::Points => record.points.unwrap_or_default().to_string(),
+                    SortField::Amount => record.bet_amount.clone(),
+                    SortField::Id => record.id.to_string(),
+                },
+                id: record.id,
+            }
+        })),
+    )
+}
 
 #[get("/{id}")]
-async fn get_bet_record_by_id(id: web::Path<i64>, data: web::Data<AppState>) -> impl Responder {
-    let id_value = id.into_inner();
+async fn get_bet_record_by_id(
+    id: web::Path<i64>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let record = bet_record::get_bet_record_by_id(&data.db_pool, id.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(record))
+}
 
-    match bet_record::get_bet_record_by_id(&data.db_pool, id_value).await {
-        Ok(record) => HttpResponse::Ok().json(record),
-        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
-    }
+#[derive(Deserialize, Serialize)]
+struct PreviewQuery {
+    /// Points to preview instead of the recorded ones.
+    points: Option<u64>,
 }
 
-#[post("")]
-async fn create_bet_record(
+#[derive(Serialize)]
+struct BetPreviewResponse {
+    bet_id: i64,
+    tier: u8,
+    bet_amount: U256,
+    reward_percentage: U256,
+    #[serde(flatten)]
+    preview: crate::tier_config::BetPreview,
+}
+
+/// Previews the outcome `resolveBet` would record for this bet, using the cached tier configuration.
+/// Points come from the query, the resolved bet or the linked match, in that order.
+#[get("/{id}/preview")]
+async fn get_bet_preview(
+    id: web::Path<i64>,
+    query: web::Query<PreviewQuery>,
     data: web::Data<AppState>,
-    bet_record: web::Json<BetRecord>,
-) -> impl Responder {
-    let bet_record = bet_record.into_inner();
-    match bet_record::create_bet_record(&data.db_pool, bet_record).await {
-        Ok(_) => HttpResponse::Created().finish(),
-        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+) -> Result<HttpResponse, Error> {
+    let id_value = id.into_inner();
+
+    let record = bet_record::get_bet_record_by_id(&data.db_pool, id_value).await?;
+    let points = match (query.points, record.status, record.points, record.match_id) {
+        (Some(points), _, _, _) => points,
+        (None, Some(BetStatus::Resolved), Some(points), _) => points as u64,
+        (None, _, _, Some(match_id)) => {
+            match match_record::get_player_point_by_match_id(&data.db_pool, match_id).await {
+                Ok(Some(points)) => points as u64,
+                Ok(None) | Err(Error::NotFound(_)) => {
+                    return Err(Error::Conflict("match has no points yet".to_string()))
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        _ => return Err(Error::Conflict("bet is not linked to a match".to_string())),
+    };
+
+    let bet_amount = U256::from_str(&record.bet_amount)
+        .map_err(|_| Error::Internal("invalid bet amount".to_string()))?;
+    let tier = record.bet_tier.map(|tier| tier as u8).unwrap_or_default();
+
+    let tier_config = data
+        .tier_config
+        .get()
+        .await
+        .map_err(|e| Error::Chain(e.to_string()))?;
+    match tier_config.preview(tier, bet_amount, U256::from(points)) {
+        Some(preview) if tier != 0 => Ok(HttpResponse::Ok().json(BetPreviewResponse {
+            bet_id: id_value,
+            tier,
+            bet_amount,
+            reward_percentage: tier_config.tiers[tier as usize].reward_percentage,
+            preview,
+        })),
+        _ => Err(Error::Conflict("bet has an unknown tier".to_string())),
     }
 }
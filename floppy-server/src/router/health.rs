@@ -0,0 +1,40 @@
+use crate::state::AppState;
+use actix_web::{get, web, HttpResponse, Responder, Scope};
+
+// Define a scope for health routes
+pub fn health_scope() -> Scope {
+    web::scope("/health")
+        .service(get_signer_health)
+        .service(get_bets_sync_health)
+        .service(get_workers_health)
+}
+
+/// Reports whether the permit signer matches the gamble and vault contracts.
+/// Answers 503 on mismatch so load balancers and monitors can alert on it.
+#[get("/signer")]
+async fn get_signer_health(data: web::Data<AppState>) -> impl Responder {
+    let report = data.signer_health.report();
+    if report.is_healthy() {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+/// Reports the bet reconciliation runs: bets checked, drift found per kind and repairs.
+#[get("/bets")]
+async fn get_bets_sync_health(data: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(data.bets_syncer.report())
+}
+
+/// Reports whether each background worker is running and still making progress.
+/// Answers 503 while any of them is restarting, stuck or stopped.
+#[get("/workers")]
+async fn get_workers_health(data: web::Data<AppState>) -> impl Responder {
+    let report = data.workers.report();
+    if report.is_healthy() {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
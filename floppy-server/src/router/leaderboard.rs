@@ -0,0 +1,110 @@
+use crate::db::leaderboard;
+use crate::error::Error;
+use crate::models::{BetTier, LeaderboardEntry};
+use crate::state::AppState;
+use actix_web::{get, web, HttpResponse, Scope};
+use serde::{Deserialize, Serialize};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+// Define a scope for leaderboard routes
+pub fn leaderboard_scope() -> Scope {
+    web::scope("/leaderboard").service(get_leaderboard)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum LeaderboardPeriod {
+    AllTime,
+    /// Since 00:00 UTC today.
+    Daily,
+    /// Since Monday 00:00 UTC.
+    Weekly,
+}
+
+impl LeaderboardPeriod {
+    /// Start of the period containing `now`, as a unix timestamp.
+    fn since(self, now: i64) -> i64 {
+        let day = now.div_euclid(SECONDS_PER_DAY);
+        match self {
+            LeaderboardPeriod::AllTime => 0,
+            LeaderboardPeriod::Daily => day * SECONDS_PER_DAY,
+            // 1970-01-01 was a Thursday, three days after a Monday
+            LeaderboardPeriod::Weekly => (day - (day + 3).rem_euclid(7)) * SECONDS_PER_DAY,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LeaderboardQuery {
+    /// Only count matches played for a bet of this tier.
+    tier: Option<BetTier>,
+    /// Starts at 1.
+    page: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct LeaderboardResponse {
+    period: LeaderboardPeriod,
+    tier: Option<BetTier>,
+    since: i64,
+    page: i64,
+    limit: i64,
+    total: i64,
+    entries: Vec<LeaderboardEntry>,
+}
+
+/// Ranks wallets by their best match of the period.
+#[get("/{period}")]
+async fn get_leaderboard(
+    period: web::Path<LeaderboardPeriod>,
+    query: web::Query<LeaderboardQuery>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let period = period.into_inner();
+    let query = query.into_inner();
+    let tier = query.tier;
+    if tier == Some(BetTier::Unknown) {
+        return Err(Error::Validation("unknown bet tier".to_string()));
+    }
+    let page = query.page.unwrap_or(1).max(1);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let since = period.since(chrono::Utc::now().timestamp());
+
+    let total = leaderboard::count_leaderboard(&data.db_pool, since, tier).await?;
+    let entries =
+        leaderboard::get_leaderboard(&data.db_pool, since, tier, limit, (page - 1) * limit).await?;
+    Ok(HttpResponse::Ok().json(LeaderboardResponse {
+        period,
+        tier,
+        since,
+        page,
+        limit,
+        total,
+        entries,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_period_since() {
+        // Wednesday 2024-10-02 15:30:00 UTC
+        let now = 1727883000;
+
+        assert_eq!(LeaderboardPeriod::AllTime.since(now), 0);
+        // 2024-10-02 00:00:00 UTC
+        assert_eq!(LeaderboardPeriod::Daily.since(now), 1727827200);
+        // Monday 2024-09-30 00:00:00 UTC
+        assert_eq!(LeaderboardPeriod::Weekly.since(now), 1727654400);
+        assert_eq!(LeaderboardPeriod::Weekly.since(1727654400), 1727654400);
+    }
+}
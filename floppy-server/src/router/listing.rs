@@ -0,0 +1,210 @@
+//! Query parameters and page shape shared by the record listings.
+//!
+//! Both `/match_record` and `/bet_record` accept `wallet`, `status`, `tier`, `match_id`, `from`,
+//! `to`, `sort`, `order`, `limit` and `cursor`, and answer a [`Page`]. Pages are keyset-based:
+//! `next_cursor` encodes the sort value and id of the last record, so inserts between requests
+//! neither skip nor repeat records.
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::models::{BetTier, RecordCursor, RecordFilter, SortField, SortOrder};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery<S> {
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub wallet: Option<String>,
+    pub status: Option<S>,
+    pub tier: Option<BetTier>,
+    pub match_id: Option<i64>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub sort: Option<SortField>,
+    pub order: Option<SortOrder>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Absent on the last page.
+    pub next_cursor: Option<String>,
+    /// Number of records passing the filters, across all pages.
+    pub total: i64,
+}
+
+impl<S: Display> ListQuery<S> {
+    /// Validates the query against the sort fields a listing supports. Returns the filter and
+    /// the page size.
+    pub fn into_filter(self, sort_fields: &[SortField]) -> Result<(RecordFilter, i64), Error> {
+        let sort = self.sort.unwrap_or(SortField::Id);
+        if !sort_fields.contains(&sort) {
+            return Err(Error::Validation(
+                format!("cannot sort by {:?}", sort).to_lowercase(),
+            ));
+        }
+        let mut filter = RecordFilter {
+            wallet: self.wallet,
+            status: self.status.map(|status| status.to_string()),
+            tier: self.tier.map(|tier| tier.to_string()),
+            match_id: self.match_id,
+            from: self.from,
+            to: self.to,
+            sort,
+            order: self.order.unwrap_or(SortOrder::Desc),
+            cursor: None,
+        };
+        if let Some(cursor) = self.cursor {
+            filter.cursor = Some(
+                decode_cursor(&filter, &cursor)
+                    .ok_or_else(|| Error::Validation("invalid cursor".to_string()))?,
+            );
+        }
+        let limit = self
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        Ok((filter, limit))
+    }
+}
+
+fn order_name(order: SortOrder) -> &'static str {
+    match order {
+        SortOrder::Asc => "asc",
+        SortOrder::Desc => "desc",
+    }
+}
+
+/// Cursors carry their sort and order, so one is never applied to a differently sorted listing.
+fn encode_cursor(filter: &RecordFilter, cursor: &RecordCursor) -> String {
+    format!(
+        "{}:{}:{}:{}",
+        filter.sort_name(),
+        order_name(filter.order),
+        cursor.sort_value,
+        cursor.id
+    )
+}
+
+fn decode_cursor(filter: &RecordFilter, cursor: &str) -> Option<RecordCursor> {
+    let mut parts = cursor.split(':');
+    let (Some(sort), Some(order), Some(sort_value), Some(id), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return None;
+    };
+    // Sort values are non-negative integers, bet amounts included
+    if sort != filter.sort_name()
+        || order != order_name(filter.order)
+        || sort_value.is_empty()
+        || !sort_value.chars().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    Some(RecordCursor {
+        sort_value: sort_value.to_string(),
+        id: id.parse().ok()?,
+    })
+}
+
+/// Builds a page from up to `limit + 1` records, the extra one only telling that more follow.
+/// `cursor_of` returns the sort value and id of a record.
+pub fn page<T>(
+    mut items: Vec<T>,
+    limit: i64,
+    total: i64,
+    filter: &RecordFilter,
+    cursor_of: impl Fn(&T) -> RecordCursor,
+) -> Page<T> {
+    let has_more = items.len() as i64 > limit;
+    items.truncate(limit as usize);
+    let next_cursor = items
+        .last()
+        .filter(|_| has_more)
+        .map(|last| encode_cursor(filter, &cursor_of(last)));
+    Page {
+        items,
+        next_cursor,
+        total,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::MatchStatus;
+
+    fn query(sort: Option<SortField>, cursor: Option<&str>) -> ListQuery<MatchStatus> {
+        ListQuery {
+            cursor: cursor.map(str::to_string),
+            limit: Some(500),
+            wallet: None,
+            status: Some(MatchStatus::OffMatch),
+            tier: None,
+            match_id: None,
+            from: None,
+            to: None,
+            sort,
+            order: None,
+        }
+    }
+
+    #[test]
+    fn test_into_filter() {
+        let (filter, limit) = query(Some(SortField::Points), None)
+            .into_filter(&[SortField::Id, SortField::Points])
+            .unwrap();
+        assert_eq!(limit, MAX_PAGE_SIZE);
+        assert_eq!(filter.status.as_deref(), Some("OffMatch"));
+        assert_eq!(filter.order, SortOrder::Desc);
+
+        assert!(query(Some(SortField::Amount), None)
+            .into_filter(&[SortField::Id])
+            .is_err());
+    }
+
+    #[test]
+    fn test_cursor() {
+        let sorts = [SortField::Id, SortField::Time];
+        let (filter, _) = query(Some(SortField::Time), None)
+            .into_filter(&sorts)
+            .unwrap();
+        let items = vec![(100, 1), (90, 2), (90, 3)];
+        let page = page(items, 2, 3, &filter, |(time, id)| RecordCursor {
+            sort_value: time.to_string(),
+            id: *id,
+        });
+        assert_eq!(page.items.len(), 2);
+        let next_cursor = page.next_cursor.unwrap();
+        assert_eq!(next_cursor, "time:desc:90:2");
+
+        let (next, _) = query(Some(SortField::Time), Some(&next_cursor))
+            .into_filter(&sorts)
+            .unwrap();
+        assert_eq!(
+            next.cursor,
+            Some(RecordCursor {
+                sort_value: "90".to_string(),
+                id: 2
+            })
+        );
+
+        // A cursor only applies to the sort it was issued for
+        assert!(query(Some(SortField::Id), Some(&next_cursor))
+            .into_filter(&sorts)
+            .is_err());
+        assert!(query(Some(SortField::Time), Some("time:desc:-1;--:2"))
+            .into_filter(&sorts)
+            .is_err());
+    }
+}
@@ -1,74 +1,268 @@
-use crate::db::bet_record;
-use crate::db::match_record::{self, create_match_with_bet_records};
-use crate::models::MatchRecord;
+use crate::auth::AuthenticatedWallet;
+use crate::db::match_record;
+use crate::error::Error;
+use crate::models::{MatchRecord, MatchStatus, RecordCursor, SortField};
+use crate::play_data::{self, ReplayError, FRAMES_PER_SECOND, MAX_FRAMES};
+use crate::router::listing::{page, ListQuery};
 use crate::state::AppState;
-use actix_web::{get, post, web, HttpResponse, Responder, Scope};
+use actix_web::{get, post, web, HttpResponse, Scope};
+use alloy::hex;
+use serde::{Deserialize, Serialize};
+
+/// Time allowed between starting a session and submitting its end, in seconds.
+const MAX_MATCH_DURATION: i64 = (MAX_FRAMES / FRAMES_PER_SECOND) as i64 + 60;
+/// Slack granted to the client clock when comparing played frames to elapsed time, in seconds.
+const CLOCK_SLACK: i64 = 5;
 
 // Define a scope for match_record routes
 pub fn match_record_scope() -> Scope {
     web::scope("/match_record")
         .service(get_all_match_records)
         .service(get_match_record_by_id)
-        .service(create_match_record_handler)
-        .service(create_match_with_bet_records_handler)
+        .service(start_match_session)
+        .service(end_match_session)
 }
 
-#[get("")]
-async fn get_all_match_records(data: web::Data<AppState>) -> impl Responder {
-    match match_record::get_all_match_records(&data.db_pool).await {
-        Ok(records) => HttpResponse::Ok().json(records),
-        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
-    }
+#[derive(Deserialize, Serialize)]
+struct StartSessionData {
+    /// Bet played for in this match, linked when the session starts or once it is indexed.
+    bet_id: Option<i64>,
 }
 
-#[post("/create_with_bet_records/{bet_id}")]
-async fn create_match_with_bet_records_handler(
+#[derive(Deserialize, Serialize)]
+struct MatchSession {
+    match_id: i64,
+    session_id: String,
+    seed: u64,
+    started_at: i64,
+    expires_at: i64,
+}
+
+#[derive(Deserialize, Serialize)]
+struct EndSessionData {
+    play_data: String,
+    player_point: i32,
+}
+
+/// Lists matches a page at a time, see [`ListQuery`] for the parameters.
+#[get("")]
+async fn get_all_match_records(
+    query: web::Query<ListQuery<MatchStatus>>,
     data: web::Data<AppState>,
-    match_record: web::Json<MatchRecord>,
-    bet_id: web::Path<i64>,
-) -> impl Responder {
-    let bet_id_value = bet_id.into_inner();
-    if bet_record::is_bet_exists(&data.db_pool, bet_id_value)
-        .await
-        .unwrap()
+) -> Result<HttpResponse, Error> {
+    let (filter, limit) =
+        query
+            .into_inner()
+            .into_filter(&[SortField::Id, SortField::Time, SortField::Points])?;
+    let total = match_record::count_match_records(&data.db_pool, &filter).await?;
+    let records = match_record::list_match_records(&data.db_pool, &filter, limit + 1).await?;
+    Ok(
+        HttpResponse::Ok().json(page(records, limit, total, &filter, |record| {
+            RecordCursor {
+                sort_value: match filter.sort {
+                    SortField::Time => record.start_time.unwrap_or_default().to_string(),
+                    SortField::Points => record.player_point.unwrap_or_default().to_string(),
+                    _ => record.id.to_string(),
+                },
+                id: record.id,
+            }
+        })),
+    )
+}
+
+/// Rejects a match that was not played by the authenticated wallet.
+fn check_player(wallet: &AuthenticatedWallet, match_record: &MatchRecord) -> Result<(), Error> {
+    if match_record
+        .wallet_id
+        .as_deref()
+        .is_some_and(|wallet_id| wallet.is(wallet_id))
     {
-        let match_id = match_record::create_match_record(&data.db_pool, match_record.into_inner())
-            .await
-            .unwrap();
-        bet_record::update_match_id(&data.db_pool, bet_id_value, match_id as i64)
-            .await
-            .unwrap();
-        HttpResponse::Created().finish()
+        Ok(())
     } else {
-        match_record::create_match_with_bet_records(
-            &data.db_pool,
-            match_record.into_inner(),
-            bet_id_value,
-        )
-        .await
-        .unwrap();
-        HttpResponse::Created().finish()
+        Err(Error::Forbidden(
+            "match was not played by this wallet".to_string(),
+        ))
+    }
+}
+
+/// Replays the play data submitted by `wallet_id` and rejects the match unless it scores
+/// `player_point`.
+fn verify_play_data(
+    wallet_id: Option<&str>,
+    play_data: &str,
+    player_point: i32,
+) -> Result<(), Error> {
+    match play_data::verify(Some(play_data), Some(player_point)) {
+        Ok(_) => Ok(()),
+        Err(
+            e @ (ReplayError::InvalidFormat(_)
+            | ReplayError::UnsupportedVersion(_)
+            | ReplayError::InvalidInputs(_)),
+        ) => Err(e.into()),
+        Err(e) => {
+            eprintln!(
+                "Rejected match of {:?}: {}",
+                wallet_id.unwrap_or_default(),
+                e
+            );
+            Err(e.into())
+        }
     }
 }
 
 #[get("/{id}")]
-async fn get_match_record_by_id(id: web::Path<i32>, data: web::Data<AppState>) -> impl Responder {
-    let id_value = id.into_inner();
+async fn get_match_record_by_id(
+    id: web::Path<i64>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let record = match_record::get_match_by_id(&data.db_pool, id.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(record))
+}
+
+/// Starts a match for the authenticated wallet: the server picks the start time and the seed
+/// the run must be played with.
+#[post("/session")]
+async fn start_match_session(
+    data: web::Data<AppState>,
+    wallet: AuthenticatedWallet,
+    session_data: web::Json<StartSessionData>,
+) -> Result<HttpResponse, Error> {
+    let session_id = hex::encode(rand::random::<[u8; 16]>());
+    // Kept positive so it survives the BIGINT column unchanged
+    let seed = (rand::random::<u64>() >> 1) as i64;
+    let match_record = match_record::start_match_session(
+        &data.db_pool,
+        &wallet.0.to_string(),
+        &session_id,
+        seed,
+        session_data.bet_id,
+    )
+    .await?;
 
-    match match_record::get_match_by_id(&data.db_pool, id_value).await {
-        Ok(record) => HttpResponse::Ok().json(record),
-        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    let started_at = match_record.start_time.unwrap_or_default();
+    Ok(HttpResponse::Created().json(MatchSession {
+        match_id: match_record.id,
+        session_id,
+        seed: seed as u64,
+        started_at,
+        expires_at: started_at + MAX_MATCH_DURATION,
+    }))
+}
+
+/// Checks submitted play data against its session: played with the session seed, no longer than
+/// the time elapsed since the session started, and scoring the claimed points.
+fn check_submission(
+    match_record: &MatchRecord,
+    end_data: &EndSessionData,
+    elapsed: i64,
+) -> Result<(), Error> {
+    let play = play_data::parse(Some(&end_data.play_data))?;
+    if Some(play.seed) != match_record.seed.map(|seed| seed as u64) {
+        return Err(Error::Unprocessable(
+            "play data seed does not match the session".to_string(),
+        ));
+    }
+    if play.frames as i64 > (elapsed + CLOCK_SLACK) * FRAMES_PER_SECOND as i64 {
+        return Err(Error::Unprocessable(
+            "play data is longer than the session".to_string(),
+        ));
     }
+    verify_play_data(
+        match_record.wallet_id.as_deref(),
+        &end_data.play_data,
+        end_data.player_point,
+    )
 }
 
-#[post("")]
-async fn create_match_record_handler(
+/// Ends a match session with its play data. Each session accepts one submission: play data
+/// failing [`check_submission`] closes the session as rejected.
+#[post("/session/{session_id}/end")]
+async fn end_match_session(
     data: web::Data<AppState>,
-    match_record: web::Json<MatchRecord>,
-) -> impl Responder {
-    let match_record = match_record.into_inner();
-    match match_record::create_match_record(&data.db_pool, match_record).await {
-        Ok(_) => HttpResponse::Created().finish(),
-        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
+    wallet: AuthenticatedWallet,
+    session_id: web::Path<String>,
+    end_data: web::Json<EndSessionData>,
+) -> Result<HttpResponse, Error> {
+    let session_id = session_id.into_inner();
+    let end_data = end_data.into_inner();
+
+    let match_record = match_record::get_match_by_session_id(&data.db_pool, &session_id).await?;
+    check_player(&wallet, &match_record)?;
+    if match_record.status != Some(MatchStatus::OnMatch) {
+        return Err(Error::Conflict("session already ended".to_string()));
+    }
+    let now = chrono::Utc::now().timestamp();
+    let started_at = match_record.start_time.unwrap_or_default();
+    if now - started_at > MAX_MATCH_DURATION {
+        return Err(Error::Expired("session expired".to_string()));
+    }
+
+    let (status, player_point, result) =
+        match check_submission(&match_record, &end_data, now - started_at) {
+            Ok(()) => (MatchStatus::OffMatch, end_data.player_point, Ok(())),
+            Err(e) => (MatchStatus::Rejected, 0, Err(e)),
+        };
+    let ended = match_record::end_match_session(
+        &data.db_pool,
+        &session_id,
+        status,
+        &end_data.play_data,
+        player_point,
+        now - MAX_MATCH_DURATION,
+    )
+    .await?;
+    if !ended {
+        // Another submission ended the session first
+        return Err(Error::Conflict("session already ended".to_string()));
+    }
+    result?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{play_data::tests::crashed_run, state::tests::test_state};
+    use actix_web::{http::StatusCode, test, App, HttpMessage};
+    use alloy::primitives::{address, Address};
+    use sqlx::PgPool;
+
+    const PLAYER: Address = address!("193542e0C9746e8a428b2a4430545AFdb87d95E8");
+
+    async fn end_session(pool: &PgPool, player_point: i32) -> StatusCode {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(test_state(pool.clone())))
+                .service(match_record_scope()),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/match_record/session/session/end")
+            .set_json(EndSessionData {
+                play_data: serde_json::to_string(&crashed_run(7)).unwrap(),
+                player_point,
+            })
+            .to_request();
+        req.extensions_mut().insert(AuthenticatedWallet(PLAYER));
+        test::call_service(&app, req).await.status()
+    }
+
+    #[sqlx::test]
+    async fn test_failed_submission_ends_session(pool: PgPool) {
+        sqlx::query(
+            "INSERT INTO match_record (id, wallet_id, start_time, player_point, status, session_id, seed) VALUES (1, $1, $2, 0, 'OnMatch', 'session', 7)",
+        )
+        .bind(PLAYER.to_string())
+        .bind(chrono::Utc::now().timestamp() - 10)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        // The run scores 0 points
+        assert!(end_session(&pool, 120).await.is_client_error());
+        let rejected = match_record::get_match_by_id(&pool, 1).await.unwrap();
+        assert_eq!(rejected.status, Some(MatchStatus::Rejected));
+
+        assert_eq!(end_session(&pool, 0).await, StatusCode::CONFLICT);
     }
 }
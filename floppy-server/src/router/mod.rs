@@ -1,3 +1,9 @@
+pub mod auth;
 pub mod bet_record;
+pub mod health;
+pub mod leaderboard;
+pub mod listing;
 pub mod match_record;
+pub mod player;
 pub mod signer;
+pub mod vault_transaction;
@@ -0,0 +1,59 @@
+use crate::auth::AuthenticatedWallet;
+use crate::db::player;
+use crate::error::Error;
+use crate::models::{Player, PlayerStats};
+use crate::presence::HEARTBEAT_TIMEOUT;
+use crate::state::AppState;
+use actix_web::{get, post, web, HttpResponse, Scope};
+use alloy::primitives::Address;
+use serde::Serialize;
+use std::str::FromStr;
+
+// Define a scope for player routes
+pub fn player_scope() -> Scope {
+    web::scope("/player")
+        .service(post_heartbeat)
+        .service(get_player_profile)
+}
+
+#[derive(Serialize)]
+struct HeartbeatResponse {
+    #[serde(flatten)]
+    player: Player,
+    /// Seconds before the player is marked offline without another heartbeat.
+    timeout: i64,
+}
+
+#[derive(Serialize)]
+struct PlayerProfile {
+    #[serde(flatten)]
+    player: Player,
+    stats: PlayerStats,
+}
+
+/// Keeps the authenticated player online.
+#[post("/heartbeat")]
+async fn post_heartbeat(
+    wallet: AuthenticatedWallet,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let player = player::record_heartbeat(&data.db_pool, &wallet.0.to_string()).await?;
+    Ok(HttpResponse::Ok().json(HeartbeatResponse {
+        player,
+        timeout: HEARTBEAT_TIMEOUT,
+    }))
+}
+
+#[get("/{wallet}")]
+async fn get_player_profile(
+    wallet: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let wallet = Address::from_str(&wallet.into_inner())
+        .map_err(|_| Error::Validation("invalid wallet address".to_string()))?;
+    let wallet_id = wallet.to_string();
+
+    let player = player::get_player(&data.db_pool, &wallet_id).await?;
+    let stats = player::get_player_stats(&data.db_pool, &wallet_id).await?;
+    Ok(HttpResponse::Ok().json(PlayerProfile { player, stats }))
+}
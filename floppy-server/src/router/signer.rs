@@ -1,113 +1,562 @@
 use std::str::FromStr;
 
 use crate::{
-    db::{bet_record, match_record},
-    signer::{sign_gamble_permit, Permit},
+    auth::AuthenticatedWallet,
+    db::{bet_record, gamble_permit, match_record, reward_ledger},
+    error::Error,
+    event_listener::FloppyVault,
+    models::{BetStatus, GamblePermitRecord, MatchStatus, VaultPermitRecord},
+    play_data,
+    signer::{sign_cancel_permit, sign_gamble_permit, sign_vault_permit, Permit, SignData},
     state::AppState,
 };
-use actix_web::{get, post, web, HttpResponse, Responder, Scope};
+use actix_web::{get, post, web, HttpResponse, Scope};
 use alloy::{
     hex,
-    primitives::{address, Address, U256},
-    signers::{local::PrivateKeySigner, Signature, Signer},
-    sol_types::{eip712_domain, SolStruct},
+    primitives::{Address, B256, U256},
+    providers::ProviderBuilder,
+    signers::Signature,
+    sol_types::{Eip712Domain, SolStruct},
+    transports::http::reqwest::Url,
 };
 use serde::{Deserialize, Serialize};
 
 // Define a scope for match_record routes
 pub fn signer_scope() -> Scope {
-    web::scope("/signer").service(get_gamble_signature)
-}
-
-// Define a struct to represent the incoming data
-#[derive(Deserialize, Serialize)]
-struct GamblePermitData {
-    bet_id: U256,
-    requester: Address,
-    receiver: Address,
-    points: U256,
-    bet_amount: U256,
+    web::scope("/signer")
+        .service(get_gamble_signature)
+        .service(get_cancel_signature)
+        .service(get_vault_signature)
 }
 
 #[derive(Deserialize, Serialize)]
 struct VaultPermitData {
-    bet_id: U256,
     requester: Address,
     recipient: Address,
     amount: U256,
 }
 
+/// Version of the signed permit response schema, bumped on breaking changes.
+const SIGNED_PERMIT_VERSION: u32 = 1;
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DomainData {
+    name: String,
+    version: String,
+    chain_id: u64,
+    verifying_contract: Address,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SignatureData {
+    r: B256,
+    s: B256,
+    v: u8,
+    packed: String,
+}
+
+/// Signed permit returned to clients, carrying every argument the contract call needs.
+#[derive(Deserialize, Serialize)]
+struct SignedPermitResponse<P> {
+    version: u32,
+    permit: P,
+    signer: Address,
+    domain: DomainData,
+    signature: SignatureData,
+}
+
+impl<P> From<SignData<P>> for SignedPermitResponse<P> {
+    fn from(sign_data: SignData<P>) -> Self {
+        let bytes: [u8; 65] = sign_data.signature.into();
+        let domain = sign_data.domain;
+        Self {
+            version: SIGNED_PERMIT_VERSION,
+            permit: sign_data.permit,
+            signer: sign_data.signer,
+            domain: DomainData {
+                name: domain.name.unwrap_or_default().into_owned(),
+                version: domain.version.unwrap_or_default().into_owned(),
+                chain_id: domain.chain_id.unwrap_or_default().to::<u64>(),
+                verifying_contract: domain.verifying_contract.unwrap_or_default(),
+            },
+            signature: SignatureData {
+                r: sign_data.signature.r().into(),
+                s: sign_data.signature.s().into(),
+                v: bytes[64],
+                packed: format!("0x{}", hex::encode(bytes)),
+            },
+        }
+    }
+}
+
+/// Rebuilds the signed permit stored for a bet.
+fn stored_gamble_permit(
+    permit: GamblePermitRecord,
+    domain: Eip712Domain,
+) -> eyre::Result<SignData> {
+    let permit_data = Permit {
+        betId: U256::from(permit.bet_id),
+        requester: Address::from_str(&permit.requester)?,
+        receiver: Address::from_str(&permit.receiver)?,
+        points: U256::from(permit.points),
+        betAmount: U256::from_str(&permit.bet_amount)?,
+        deadline: U256::from(permit.deadline),
+    };
+    let signature = Signature::from_str(&permit.signature)?;
+    let signer =
+        signature.recover_address_from_prehash(&permit_data.eip712_signing_hash(&domain))?;
+    Ok(SignData {
+        permit: permit_data,
+        signature,
+        signer,
+        domain,
+    })
+}
+
+/// Signs the `resolveBet` permit of a pending bet with the points of its ended match.
+///
+/// The caller must be the bet requester and the match must have been played by them, and a bet
+/// is signed for a single score: while its permit is valid the same permit is returned, and once
+/// expired it is only re-signed for the same points.
 #[get("/gamble-signature/{bet_id}")]
-async fn get_gamble_signature(bet_id: web::Path<i64>, data: web::Data<AppState>) -> impl Responder {
+async fn get_gamble_signature(
+    bet_id: web::Path<i64>,
+    wallet: AuthenticatedWallet,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    if !data.signer_health.can_sign_gamble() {
+        return Err(Error::SignerMismatch);
+    }
     let bet_id_value = bet_id.into_inner();
-    let bet_record = bet_record::get_bet_record_by_id(&data.db_pool, bet_id_value)
-        .await
-        .unwrap();
-    let points =
-        match_record::get_player_point_by_match_id(&data.db_pool, bet_record.match_id as i32)
-            .await
-            .unwrap();
-    let result = sign_gamble_permit(
+    let bet_record = bet_record::get_bet_record_by_id(&data.db_pool, bet_id_value).await?;
+    if bet_record.status != Some(BetStatus::Pending) {
+        return Err(Error::Conflict("bet is not pending".to_string()));
+    }
+    let (Ok(requester), Ok(receiver)) = (
+        Address::from_str(&bet_record.requester_address),
+        Address::from_str(&bet_record.receiver_address),
+    ) else {
+        return Err(Error::Conflict("bet is not indexed yet".to_string()));
+    };
+    if requester != wallet.0 {
+        return Err(Error::Forbidden(
+            "bet was not placed by this wallet".to_string(),
+        ));
+    }
+    // The indexer stores the exact on-chain `BetInfo.amount`, which the contract hashes
+    let bet_amount = match U256::from_str(&bet_record.bet_amount) {
+        Ok(bet_amount) if !bet_amount.is_zero() => bet_amount,
+        _ => return Err(Error::Conflict("bet is not indexed yet".to_string())),
+    };
+    let Some(match_id) = bet_record.match_id else {
+        return Err(Error::Conflict("bet is not linked to a match".to_string()));
+    };
+    let match_record = match_record::get_match_by_id(&data.db_pool, match_id).await?;
+    let player = match_record
+        .wallet_id
+        .as_deref()
+        .and_then(|wallet_id| Address::from_str(wallet_id).ok());
+    if player != Some(requester) {
+        return Err(Error::Forbidden(
+            "match was not played by the bet requester".to_string(),
+        ));
+    }
+    // Only matches played through a session that has ended carry trustworthy points
+    if match_record.session_id.is_none()
+        || match_record.status != Some(MatchStatus::OffMatch)
+        || match_record.end_time.is_none()
+    {
+        return Err(Error::Conflict("match has not ended".to_string()));
+    }
+    // Matches stored before replay verification existed are checked here as well
+    let points = play_data::verify(match_record.play_data.as_deref(), match_record.player_point)?;
+
+    let domain = data.config.gamble_domain();
+    if let Some(stored) = gamble_permit::get_gamble_permit(&data.db_pool, bet_id_value).await? {
+        if stored.match_id != match_record.id || stored.points != points as i64 {
+            return Err(Error::Conflict(
+                "bet was already signed for another result".to_string(),
+            ));
+        }
+        if stored.deadline > chrono::Utc::now().timestamp() {
+            let sign_data =
+                stored_gamble_permit(stored, domain).map_err(|e| Error::Internal(e.to_string()))?;
+            return Ok(HttpResponse::Ok().json(SignedPermitResponse::from(sign_data)));
+        }
+    }
+
+    let sign_data = sign_gamble_permit(
+        &data.signer,
+        &domain,
         U256::from(bet_id_value),
-        Address::from_str(&bet_record.requester_address).unwrap(),
-        Address::from_str(&bet_record.receiver_address).unwrap(),
-        U256::from(points.unwrap_or(0)),
-        U256::from(10000000000000000001 as i128),
+        requester,
+        receiver,
+        U256::from(points),
+        bet_amount,
     )
-    .await;
+    .await
+    .map_err(|e| Error::Signing(e.to_string()))?;
+    let response = SignedPermitResponse::from(sign_data);
+
+    let permit = GamblePermitRecord {
+        bet_id: bet_id_value,
+        match_id: match_record.id,
+        requester: requester.to_string(),
+        receiver: receiver.to_string(),
+        points: points as i64,
+        bet_amount: bet_amount.to_string(),
+        deadline: response.permit.deadline.to::<i64>(),
+        signature: response.signature.packed.clone(),
+    };
+    if !gamble_permit::save_gamble_permit(&data.db_pool, &permit).await? {
+        // A concurrent request stored its permit first
+        return Err(Error::Conflict(
+            "bet was already signed, retry to fetch the permit".to_string(),
+        ));
+    }
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Signs a `cancelBet(betId, signature)` permit for a pending bet of the caller whose match has
+/// not started.
+#[get("/cancel-signature/{bet_id}")]
+async fn get_cancel_signature(
+    bet_id: web::Path<i64>,
+    wallet: AuthenticatedWallet,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    if !data.signer_health.can_sign_gamble() {
+        return Err(Error::SignerMismatch);
+    }
+    let bet_id_value = bet_id.into_inner();
+    let requester = wallet.0;
 
-    match result {
-        Ok(sign_data) => {
-            let bytes: [u8; 65] = sign_data.signature.into();
-            HttpResponse::Ok().json(format!("0x{}", hex::encode(bytes)))
+    let bet_record = bet_record::get_bet_record_by_id(&data.db_pool, bet_id_value).await?;
+    if bet_record.status != Some(BetStatus::Pending) {
+        return Err(Error::Conflict("bet is not pending".to_string()));
+    }
+    if Address::from_str(&bet_record.requester_address).ok() != Some(requester) {
+        return Err(Error::Forbidden(
+            "requester does not match the bet".to_string(),
+        ));
+    }
+    if let Some(match_id) = bet_record.match_id {
+        match match_record::get_match_by_id(&data.db_pool, match_id).await {
+            Ok(match_record)
+                if match_record
+                    .start_time
+                    .is_some_and(|start_time| start_time <= chrono::Utc::now().timestamp()) =>
+            {
+                return Err(Error::Conflict("match already started".to_string()));
+            }
+            Ok(_) | Err(Error::NotFound(_)) => (),
+            Err(e) => return Err(e),
         }
-        Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
     }
+
+    let sign_data = sign_cancel_permit(
+        &data.signer,
+        &data.config.gamble_domain(),
+        U256::from(bet_id_value),
+        requester,
+    )
+    .await
+    .map_err(|e| Error::Signing(e.to_string()))?;
+    Ok(HttpResponse::Ok().json(SignedPermitResponse::from(sign_data)))
 }
 
-pub fn recover_gamble_signature(
-    signature: &str,
-    data: Permit,
-) -> Result<Address, alloy::primitives::SignatureError> {
-    let domain = eip712_domain! {
-        name: "FloppyGamble",
-        version: "1",
-        chain_id: 2021,
-        verifying_contract: address!("ec6Be1D0c53489dE129b2C13ac3EDb393865c22F"),
-    };
+/// Signs a `permitRewardWithdraw` permit for the requester's current vault nonce, as long as
+/// the caller is the requester and the amount is covered by their off-chain reward ledger.
+#[post("/vault-signature")]
+async fn get_vault_signature(
+    permit_data: web::Json<VaultPermitData>,
+    wallet: AuthenticatedWallet,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    if !data.signer_health.can_sign_vault() {
+        return Err(Error::SignerMismatch);
+    }
+    let permit_data = permit_data.into_inner();
+    if permit_data.requester != wallet.0 {
+        return Err(Error::Forbidden(
+            "requester is not the authenticated wallet".to_string(),
+        ));
+    }
 
-    let hash = data.eip712_signing_hash(&domain);
-    let signature = Signature::from_str(signature).unwrap();
+    let url = Url::parse(&data.config.rpc_url).map_err(|e| Error::Internal(e.to_string()))?;
+    let vault = FloppyVault::new(
+        data.config.vault.address,
+        ProviderBuilder::new().on_http(url),
+    );
+    let nonce = vault
+        .getUserNonce(permit_data.requester)
+        .call()
+        .await
+        .map_err(|e| Error::Chain(e.to_string()))?
+        ._0;
 
-    let address = signature.recover_address_from_prehash(&hash)?;
-    Ok(address)
+    let sign_data = sign_vault_permit(
+        &data.signer,
+        &data.config.vault_domain(),
+        permit_data.requester,
+        permit_data.recipient,
+        nonce,
+        permit_data.amount,
+    )
+    .await
+    .map_err(|e| Error::Signing(e.to_string()))?;
+    let response = SignedPermitResponse::from(sign_data);
+
+    let vault_permit = VaultPermitRecord {
+        requester: response.permit.requester.to_string(),
+        recipient: response.permit.recipient.to_string(),
+        nonce: nonce.to::<i64>(),
+        amount: response.permit.amount.to_string(),
+        deadline: response.permit.deadline.to::<i64>(),
+        signature: response.signature.packed.clone(),
+    };
+    if !reward_ledger::reserve_vault_permit(&data.db_pool, &vault_permit).await? {
+        return Err(Error::Conflict("insufficient reward balance".to_string()));
+    }
+    Ok(HttpResponse::Ok().json(response))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use actix_web::{test, App};
-    use alloy::primitives::{Address, U256};
+    use crate::{
+        play_data::tests::crashed_run,
+        signer::{cancel_permit_signing_hash, tests::test_signer, CancelPermit},
+        state::tests::test_state,
+    };
+    use actix_web::{http::StatusCode, test, App, HttpMessage};
+    use alloy::{
+        primitives::{address, Address, U256},
+        sol_types::eip712_domain,
+    };
+    use sqlx::PgPool;
 
-    #[actix_web::test]
-    async fn test_get_gamble_signature() {
-        let app = test::init_service(App::new().service(signer_scope())).await;
-
-        let permit_data = GamblePermitData {
-            bet_id: U256::from(1),
-            requester: Address::ZERO,
-            receiver: Address::ZERO,
-            points: U256::from(10),
-            bet_amount: U256::from(100),
-        };
+    fn recover_gamble_signature(
+        domain: &Eip712Domain,
+        signature: &str,
+        data: Permit,
+    ) -> Result<Address, alloy::primitives::SignatureError> {
+        let hash = data.eip712_signing_hash(domain);
+        let signature = Signature::from_str(signature)?;
 
-        let req = test::TestRequest::post()
-            .uri("/signer/gamble-signature")
-            .set_json(&permit_data)
-            .to_request();
+        let address = signature.recover_address_from_prehash(&hash)?;
+        Ok(address)
+    }
+
+    fn recover_cancel_signature(
+        domain: &Eip712Domain,
+        signature: &str,
+        data: CancelPermit,
+    ) -> Result<Address, alloy::primitives::SignatureError> {
+        let hash = cancel_permit_signing_hash(&data, domain);
+        let signature = Signature::from_str(signature)?;
 
+        let address = signature.recover_address_from_prehash(&hash)?;
+        Ok(address)
+    }
+
+    fn gamble_domain() -> Eip712Domain {
+        eip712_domain! {
+            name: "FloppyGamble",
+            version: "1",
+            chain_id: 2021,
+            verifying_contract: address!("ec6Be1D0c53489dE129b2C13ac3EDb393865c22F"),
+        }
+    }
+
+    const PLAYER: Address = address!("193542e0C9746e8a428b2a4430545AFdb87d95E8");
+
+    /// Stores bet 1 of `PLAYER` linked to match 1, which is either ended with a run of 0 points
+    /// or still on match.
+    async fn insert_bet(pool: &PgPool, bet_status: BetStatus, match_status: MatchStatus) {
+        let ended = match_status == MatchStatus::OffMatch;
+        let play_data = serde_json::to_string(&crashed_run(7)).unwrap();
+        sqlx::query(
+            "INSERT INTO match_record (id, wallet_id, start_time, end_time, play_data, player_point, status, session_id, seed)
+            VALUES (1, $1, 0, $2, $3, 0, $4, 'session', 7)",
+        )
+        .bind(PLAYER.to_string())
+        .bind(ended.then_some(60_i64))
+        .bind(ended.then_some(play_data))
+        .bind(match_status.to_string())
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO bet_record (id, match_id, requester_address, receiver_address, bet_amount, dead_line, timestamp, status)
+            VALUES (1, 1, $1, $1, 10, 0, 0, $2)",
+        )
+        .bind(PLAYER.to_string())
+        .bind(bet_status.to_string())
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn get_gamble_signature(
+        pool: &PgPool,
+        wallet: Address,
+    ) -> (StatusCode, serde_json::Value) {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(test_state(pool.clone())))
+                .service(signer_scope()),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/signer/gamble-signature/1")
+            .to_request();
+        req.extensions_mut().insert(AuthenticatedWallet(wallet));
         let resp = test::call_service(&app, req).await;
+        let status = resp.status();
+        (status, test::read_body_json(resp).await)
+    }
+
+    #[sqlx::test]
+    async fn test_gamble_signature_wrong_wallet(pool: PgPool) {
+        insert_bet(&pool, BetStatus::Pending, MatchStatus::OffMatch).await;
+        let (status, _) = get_gamble_signature(&pool, Address::ZERO).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn test_gamble_signature_bet_not_pending(pool: PgPool) {
+        insert_bet(&pool, BetStatus::Canceled, MatchStatus::OffMatch).await;
+        let (status, _) = get_gamble_signature(&pool, PLAYER).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[sqlx::test]
+    async fn test_gamble_signature_session_not_ended(pool: PgPool) {
+        insert_bet(&pool, BetStatus::Pending, MatchStatus::OnMatch).await;
+        let (status, body) = get_gamble_signature(&pool, PLAYER).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["message"], "match has not ended");
+    }
+
+    #[sqlx::test]
+    async fn test_gamble_signature_other_points(pool: PgPool) {
+        insert_bet(&pool, BetStatus::Pending, MatchStatus::OffMatch).await;
+        let stored = GamblePermitRecord {
+            bet_id: 1,
+            match_id: 1,
+            requester: PLAYER.to_string(),
+            receiver: PLAYER.to_string(),
+            points: 5,
+            bet_amount: "10".to_string(),
+            deadline: 0,
+            signature: "0x".to_string(),
+        };
+        assert!(gamble_permit::save_gamble_permit(&pool, &stored)
+            .await
+            .unwrap());
+
+        let (status, _) = get_gamble_signature(&pool, PLAYER).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[sqlx::test]
+    async fn test_gamble_signature_returns_stored_permit(pool: PgPool) {
+        insert_bet(&pool, BetStatus::Pending, MatchStatus::OffMatch).await;
+        let (status, first) = get_gamble_signature(&pool, PLAYER).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(first["permit"]["points"], "0x0");
+        assert_eq!(
+            first["signer"],
+            serde_json::to_value(test_signer().address()).unwrap()
+        );
+
+        let (status, second) = get_gamble_signature(&pool, PLAYER).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(second["signature"], first["signature"]);
+        assert_eq!(second["permit"], first["permit"]);
+    }
+
+    #[actix_web::test]
+    async fn test_recover_cancel_signature() {
+        let domain = gamble_domain();
+        let signer = test_signer();
+        let requester = address!("193542e0C9746e8a428b2a4430545AFdb87d95E8");
+        let sign_data = sign_cancel_permit(&signer, &domain, U256::from(7), requester)
+            .await
+            .unwrap();
+        let bytes: [u8; 65] = sign_data.signature.into();
+
+        let recovered = recover_cancel_signature(
+            &domain,
+            &format!("0x{}", hex::encode(bytes)),
+            sign_data.permit,
+        )
+        .unwrap();
+
+        assert_eq!(recovered, signer.address());
+    }
+
+    #[actix_web::test]
+    async fn test_signed_permit_response() {
+        let domain = gamble_domain();
+        let requester = address!("193542e0C9746e8a428b2a4430545AFdb87d95E8");
+        let sign_data = sign_gamble_permit(
+            &test_signer(),
+            &domain,
+            U256::from(1),
+            requester,
+            requester,
+            U256::from(120),
+            U256::from(10),
+        )
+        .await
+        .unwrap();
+
+        let response = SignedPermitResponse::from(sign_data);
+
+        assert_eq!(response.version, SIGNED_PERMIT_VERSION);
+        assert_eq!(response.domain.name, "FloppyGamble");
+        assert_eq!(response.domain.chain_id, 2021);
+        assert!(response.permit.deadline > U256::ZERO);
+        assert!(response.signature.v == 27 || response.signature.v == 28);
+        let recovered =
+            recover_gamble_signature(&domain, &response.signature.packed, response.permit).unwrap();
+        assert_eq!(recovered, response.signer);
+    }
+
+    #[actix_web::test]
+    async fn test_stored_gamble_permit() {
+        let domain = gamble_domain();
+        let requester = address!("193542e0C9746e8a428b2a4430545AFdb87d95E8");
+        let sign_data = sign_gamble_permit(
+            &test_signer(),
+            &domain,
+            U256::from(3),
+            requester,
+            requester,
+            U256::from(42),
+            U256::from(10),
+        )
+        .await
+        .unwrap();
+        let response = SignedPermitResponse::from(sign_data);
+
+        let stored = GamblePermitRecord {
+            bet_id: 3,
+            match_id: 1,
+            requester: requester.to_string(),
+            receiver: requester.to_string(),
+            points: 42,
+            bet_amount: "10".to_string(),
+            deadline: response.permit.deadline.to::<i64>(),
+            signature: response.signature.packed.clone(),
+        };
+        let rebuilt = SignedPermitResponse::from(stored_gamble_permit(stored, domain).unwrap());
 
-        println!("resp: {:?}", resp.into_body());
+        assert_eq!(rebuilt.signer, response.signer);
+        assert_eq!(rebuilt.signature.packed, response.signature.packed);
+        assert_eq!(rebuilt.permit.points, U256::from(42));
     }
 }
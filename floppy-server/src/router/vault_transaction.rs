@@ -0,0 +1,21 @@
+use crate::db::vault_transaction;
+use crate::error::Error;
+use crate::state::AppState;
+use actix_web::{get, web, HttpResponse, Scope};
+
+// Define a scope for vault_transaction routes
+pub fn vault_transaction_scope() -> Scope {
+    web::scope("/vault_transaction").service(get_vault_transactions_by_wallet)
+}
+
+#[get("/{wallet_id}")]
+async fn get_vault_transactions_by_wallet(
+    wallet_id: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let wallet_id = wallet_id.into_inner();
+
+    let records =
+        vault_transaction::get_vault_transactions_by_wallet(&data.db_pool, &wallet_id).await?;
+    Ok(HttpResponse::Ok().json(records))
+}
@@ -1,15 +1,20 @@
-//! Example of signing a permit hash using a wallet.
+//! EIP-712 permits of the gamble and vault contracts and the functions signing them.
+
+pub mod backend;
+pub mod health;
 
-use actix_web::cookie::time::format_description::modifier::UnixTimestamp;
 use alloy::{
-    primitives::{address, Address, U256},
-    signers::{local::PrivateKeySigner, Signature, Signer},
+    primitives::{keccak256, Address, B256, U256},
+    signers::Signature,
     sol,
-    sol_types::{eip712_domain, SolStruct},
+    sol_types::{Eip712Domain, SolStruct, SolValue},
 };
 use eyre::Result;
 use serde::{Deserialize, Serialize};
 
+pub use backend::PermitSigner;
+pub use health::SignerHealth;
+
 sol! {
     #[allow(missing_docs)]
     #[derive(Deserialize, Serialize)]
@@ -23,32 +28,71 @@ sol! {
     }
 }
 
-pub struct SignData {
-    pub permit: Permit,
+/// Reward withdrawal permit of `FloppyVault`, whose EIP-712 type is also named `Permit`.
+pub mod vault {
+    use alloy::sol;
+    use serde::{Deserialize, Serialize};
+
+    sol! {
+        #[allow(missing_docs)]
+        #[derive(Deserialize, Serialize)]
+        struct Permit {
+            address requester;
+            address recipient;
+            uint256 nonce;
+            uint256 amount;
+            uint256 deadline;
+        }
+    }
+}
+
+pub use vault::Permit as VaultPermit;
+
+/// Mirrors `FloppyGamble.CANCEL_PERMIT_TYPEHASH`, which the deployed contract sets to zero
+/// instead of `keccak256("Permit(uint256 betId,address requester)")`.
+pub const CANCEL_PERMIT_TYPEHASH: B256 = B256::ZERO;
+
+sol! {
+    #[allow(missing_docs)]
+    #[derive(Deserialize, Serialize)]
+    struct CancelPermit {
+        uint256 betId;
+        address requester;
+    }
+}
+
+/// EIP-712 digest checked by `cancelBet(betId, signature)`. Computed by hand because the
+/// contract hashes the struct with `CANCEL_PERMIT_TYPEHASH` rather than the derived type hash.
+pub fn cancel_permit_signing_hash(permit: &CancelPermit, domain: &Eip712Domain) -> B256 {
+    let struct_hash =
+        keccak256((CANCEL_PERMIT_TYPEHASH, permit.betId, permit.requester).abi_encode());
+    keccak256(
+        [
+            &[0x19, 0x01][..],
+            domain.separator().as_slice(),
+            struct_hash.as_slice(),
+        ]
+        .concat(),
+    )
+}
+
+/// A signed permit together with everything needed to verify and submit it.
+pub struct SignData<P = Permit> {
+    pub permit: P,
     pub signature: Signature,
+    pub signer: Address,
+    pub domain: Eip712Domain,
 }
 
 pub async fn sign_gamble_permit(
+    signer: &PermitSigner,
+    domain: &Eip712Domain,
     bet_id: U256,
     requester: Address,
     receiver: Address,
     points: U256,
     bet_amount: U256,
 ) -> Result<SignData> {
-    dotenv::dotenv().ok();
-
-    let domain = eip712_domain! {
-        name: "FloppyGamble",
-        version: "1",
-        chain_id: 2021,
-        verifying_contract: address!("ec6Be1D0c53489dE129b2C13ac3EDb393865c22F"),
-    };
-
-    let signer: PrivateKeySigner = std::env::var("SIGNER_PK")
-        .expect("SIGNER_PK must be set")
-        .parse()
-        .expect("should parse private key");
-
     let deadline = U256::from(chrono::Utc::now().timestamp() + 3600); // 1 hour in seconds
 
     let permit = Permit {
@@ -57,26 +101,100 @@ pub async fn sign_gamble_permit(
         receiver,
         points,
         betAmount: bet_amount,
-        deadline: deadline,
+        deadline,
     };
 
     // Derive the EIP-712 signing hash.
-    let hash = permit.eip712_signing_hash(&domain);
+    let hash = permit.eip712_signing_hash(domain);
 
-    // Sign the hash asynchronously with the wallet.
-    let signature = signer.sign_hash(&hash).await?;
-    Ok(SignData { permit, signature })
+    // Sign the hash asynchronously with the active backend.
+    let backend = signer.active();
+    let signature = backend.sign_hash(&hash).await?;
+    Ok(SignData {
+        permit,
+        signature,
+        signer: backend.address(),
+        domain: domain.clone(),
+    })
 }
 
-#[cfg(test)]
-mod tests {
-    use std::ops::Add;
+pub async fn sign_cancel_permit(
+    signer: &PermitSigner,
+    domain: &Eip712Domain,
+    bet_id: U256,
+    requester: Address,
+) -> Result<SignData<CancelPermit>> {
+    let permit = CancelPermit {
+        betId: bet_id,
+        requester,
+    };
+
+    let hash = cancel_permit_signing_hash(&permit, domain);
+    let backend = signer.active();
+    let signature = backend.sign_hash(&hash).await?;
+    Ok(SignData {
+        permit,
+        signature,
+        signer: backend.address(),
+        domain: domain.clone(),
+    })
+}
+
+pub async fn sign_vault_permit(
+    signer: &PermitSigner,
+    domain: &Eip712Domain,
+    requester: Address,
+    recipient: Address,
+    nonce: U256,
+    amount: U256,
+) -> Result<SignData<VaultPermit>> {
+    let deadline = U256::from(chrono::Utc::now().timestamp() + 3600); // 1 hour in seconds
+
+    let permit = VaultPermit {
+        requester,
+        recipient,
+        nonce,
+        amount,
+        deadline,
+    };
 
+    let hash = permit.eip712_signing_hash(domain);
+    let backend = signer.active();
+    let signature = backend.sign_hash(&hash).await?;
+    Ok(SignData {
+        permit,
+        signature,
+        signer: backend.address(),
+        domain: domain.clone(),
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
     use super::*;
-    use alloy::signers::local::PrivateKeySigner;
+    use alloy::{
+        primitives::{address, b256},
+        signers::local::PrivateKeySigner,
+        sol_types::eip712_domain,
+    };
+    use std::sync::Arc;
+
+    pub(crate) fn test_signer() -> PermitSigner {
+        let key: PrivateKeySigner =
+            "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+                .parse()
+                .unwrap();
+        PermitSigner::new(vec![Arc::new(backend::LocalKeyBackend::new(key))]).unwrap()
+    }
 
     #[tokio::test]
     async fn test_sign_gamble_permit() {
+        let domain = eip712_domain! {
+            name: "FloppyGamble",
+            version: "1",
+            chain_id: 2021,
+            verifying_contract: address!("ec6Be1D0c53489dE129b2C13ac3EDb393865c22F"),
+        };
         // Define test inputs
         let bet_id = U256::from(1);
         let requester = address!("193542e0C9746e8a428b2a4430545AFdb87d95E8");
@@ -84,11 +202,55 @@ mod tests {
         let points = U256::from(100);
         let bet_amount: alloy_primitives::Uint<256, 4> = U256::from(10);
 
-        // Call the function
-        let result = sign_gamble_permit(bet_id, requester, receiver, points, bet_amount).await;
+        let signer = test_signer();
+        let result = sign_gamble_permit(
+            &signer, &domain, bet_id, requester, receiver, points, bet_amount,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result.permit.points, points);
+        let recovered = result
+            .signature
+            .recover_address_from_prehash(&result.permit.eip712_signing_hash(&domain))
+            .unwrap();
+        assert_eq!(recovered, result.signer);
+        assert_eq!(recovered, signer.address());
+    }
+
+    #[tokio::test]
+    async fn test_sign_vault_permit() {
+        let domain = eip712_domain! {
+            name: "FloppyVault",
+            version: "1",
+            chain_id: 2021,
+            verifying_contract: address!("A3378Fe70b19cB20FD278EaA60F6784bcb9372Ca"),
+        };
+        let requester = address!("193542e0C9746e8a428b2a4430545AFdb87d95E8");
+        let recipient = address!("193542e0C9746e8a428b2a4430545AFdb87d95E8");
+
+        let signer = test_signer();
+        let result = sign_vault_permit(
+            &signer,
+            &domain,
+            requester,
+            recipient,
+            U256::from(0),
+            U256::from(100),
+        )
+        .await
+        .unwrap();
 
-        // Assert the result
-        assert!(result.is_ok());
-        let signature = result.unwrap();
+        // Must match `FloppyVault.PERMIT_TYPEHASH`
+        assert_eq!(
+            result.permit.eip712_type_hash(),
+            b256!("b365888e64ab7bc61fb16d9b1949494d2eb12e26fcdf26e14b500893673a5a59")
+        );
+        let recovered = result
+            .signature
+            .recover_address_from_prehash(&result.permit.eip712_signing_hash(&domain))
+            .unwrap();
+        assert_eq!(recovered, result.signer);
+        assert_eq!(recovered, signer.address());
     }
 }
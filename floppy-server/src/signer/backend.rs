@@ -0,0 +1,355 @@
+//! Backends holding the key that signs permits.
+//!
+//! A backend is either a local private key, an encrypted JSON keystore decrypted at startup, or a
+//! remote signer reached over HTTP/JSON-RPC. Several backends can be configured at once so the key
+//! can be rotated: [`PermitSigner`] signs with the one `FloppyGamble.getSigner()` currently accepts.
+
+use std::sync::{Arc, RwLock};
+
+use alloy::{
+    primitives::{Address, B256},
+    providers::ProviderBuilder,
+    signers::{local::PrivateKeySigner, Signature, Signer},
+    sol,
+    transports::http::reqwest::{Client, Url},
+};
+use async_trait::async_trait;
+use eyre::{bail, eyre, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::SignerConfig;
+
+sol! {
+    #[allow(missing_docs)]
+    #[sol(rpc)]
+    interface IPermitSigner {
+        function getSigner() external view returns (address);
+
+        event SignerUpdated(address indexed signer);
+    }
+}
+
+/// Signs EIP-712 digests with a single key.
+#[async_trait]
+pub trait SignerBackend: Send + Sync {
+    fn address(&self) -> Address;
+
+    async fn sign_hash(&self, hash: &B256) -> Result<Signature>;
+}
+
+/// Private key held in memory, read from an environment variable or decrypted from a keystore.
+pub struct LocalKeyBackend {
+    signer: PrivateKeySigner,
+}
+
+impl LocalKeyBackend {
+    pub fn new(signer: PrivateKeySigner) -> Self {
+        Self { signer }
+    }
+
+    pub fn from_env(private_key_env: &str) -> Result<Self> {
+        let private_key =
+            std::env::var(private_key_env).map_err(|_| eyre!("{} must be set", private_key_env))?;
+        let signer = private_key
+            .parse()
+            .map_err(|e| eyre!("invalid private key in {}: {}", private_key_env, e))?;
+        Ok(Self::new(signer))
+    }
+
+    pub fn from_keystore(path: &str, password_env: &str) -> Result<Self> {
+        let password =
+            std::env::var(password_env).map_err(|_| eyre!("{} must be set", password_env))?;
+        let signer = PrivateKeySigner::decrypt_keystore(path, password)
+            .map_err(|e| eyre!("cannot decrypt keystore {}: {}", path, e))?;
+        Ok(Self::new(signer))
+    }
+}
+
+#[async_trait]
+impl SignerBackend for LocalKeyBackend {
+    fn address(&self) -> Address {
+        self.signer.address()
+    }
+
+    async fn sign_hash(&self, hash: &B256) -> Result<Signature> {
+        Ok(self.signer.sign_hash(hash).await?)
+    }
+}
+
+/// Signer living in another process, e.g. an HSM or KMS proxy. It speaks JSON-RPC 2.0 over HTTP:
+/// `signer_address` returns the signing address and `signer_signHash` takes a 32-byte digest and
+/// returns the 65-byte signature, both hex encoded.
+pub struct RemoteBackend {
+    client: Client,
+    url: Url,
+    address: Address,
+}
+
+#[derive(Deserialize, Serialize)]
+struct JsonRpcResponse {
+    #[serde(default)]
+    result: Option<Value>,
+    #[serde(default)]
+    error: Option<Value>,
+}
+
+impl RemoteBackend {
+    /// Connects to the remote signer, asking it for its address unless one is configured.
+    pub async fn connect(url: &str, address: Option<Address>) -> Result<Self> {
+        let mut backend = Self {
+            client: Client::new(),
+            url: Url::parse(url)?,
+            address: address.unwrap_or_default(),
+        };
+        let remote_address: Address =
+            serde_json::from_value(backend.call("signer_address", json!([])).await?)?;
+        if address.is_some_and(|address| address != remote_address) {
+            bail!(
+                "remote signer at {} signs as {} but {} is configured",
+                url,
+                remote_address,
+                backend.address
+            );
+        }
+        backend.address = remote_address;
+        Ok(backend)
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let response: JsonRpcResponse = self
+            .client
+            .post(self.url.clone())
+            .json(&json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(error) = response.error {
+            bail!("remote signer {} failed: {}", method, error);
+        }
+        response
+            .result
+            .ok_or_else(|| eyre!("remote signer {} returned no result", method))
+    }
+}
+
+#[async_trait]
+impl SignerBackend for RemoteBackend {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    async fn sign_hash(&self, hash: &B256) -> Result<Signature> {
+        let result = self.call("signer_signHash", json!([hash])).await?;
+        let signature: Signature = result
+            .as_str()
+            .ok_or_else(|| eyre!("remote signer returned a non-string signature"))?
+            .parse()?;
+        // Never hand out a signature the contract would reject
+        if signature.recover_address_from_prehash(hash)? != self.address {
+            bail!("remote signer returned a signature from another key");
+        }
+        Ok(signature)
+    }
+}
+
+/// The configured signing backends, one of which is active at a time.
+pub struct PermitSigner {
+    backends: Vec<Arc<dyn SignerBackend>>,
+    active: RwLock<usize>,
+}
+
+impl PermitSigner {
+    pub fn new(backends: Vec<Arc<dyn SignerBackend>>) -> Result<Self> {
+        if backends.is_empty() {
+            bail!("at least one signer must be configured");
+        }
+        Ok(Self {
+            backends,
+            active: RwLock::new(0),
+        })
+    }
+
+    /// Loads every configured backend. The first one is active until [`Self::sync_with_contract`].
+    pub async fn load(configs: &[SignerConfig]) -> Result<Self> {
+        let mut backends: Vec<Arc<dyn SignerBackend>> = Vec::with_capacity(configs.len());
+        for config in configs {
+            let backend: Arc<dyn SignerBackend> = match config {
+                SignerConfig::Local { private_key_env } => {
+                    Arc::new(LocalKeyBackend::from_env(private_key_env)?)
+                }
+                SignerConfig::Keystore { path, password_env } => {
+                    Arc::new(LocalKeyBackend::from_keystore(path, password_env)?)
+                }
+                SignerConfig::Remote { url, address } => {
+                    Arc::new(RemoteBackend::connect(url, *address).await?)
+                }
+            };
+            backends.push(backend);
+        }
+        Self::new(backends)
+    }
+
+    /// The backend permits are currently signed with.
+    pub fn active(&self) -> Arc<dyn SignerBackend> {
+        let active = *self.active.read().unwrap();
+        self.backends[active].clone()
+    }
+
+    pub fn address(&self) -> Address {
+        self.active().address()
+    }
+
+    /// Activates the backend whose address is `signer`. Returns false if none holds that key.
+    pub fn activate(&self, signer: Address) -> bool {
+        match self
+            .backends
+            .iter()
+            .position(|backend| backend.address() == signer)
+        {
+            Some(index) => {
+                *self.active.write().unwrap() = index;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Switches to the backend holding `on_chain`, logging the rotation. Returns false if no
+    /// configured backend holds that key, in which case the active one is kept.
+    pub fn follow(&self, on_chain: Address) -> bool {
+        let previous = self.address();
+        if !self.activate(on_chain) {
+            return false;
+        }
+        if previous != on_chain {
+            println!("Rotated permit signer from {} to {}", previous, on_chain);
+        }
+        true
+    }
+
+    /// Reads `getSigner()` from the gamble contract and switches to the matching backend, so a
+    /// `setSigner` rotation is picked up without a restart. Returns the on-chain signer.
+    pub async fn sync_with_contract(&self, rpc_url: &str, gamble: Address) -> Result<Address> {
+        let provider = ProviderBuilder::new().on_http(Url::parse(rpc_url)?);
+        let on_chain = IPermitSigner::new(gamble, provider)
+            .getSigner()
+            .call()
+            .await?
+            ._0;
+        if !self.follow(on_chain) {
+            bail!(
+                "contract signer {} is not one of the configured signers, still signing as {}",
+                on_chain,
+                self.address()
+            );
+        }
+        Ok(on_chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::{b256, keccak256};
+    use warp::Filter;
+
+    const TEST_PK: &str = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+    fn test_signer() -> PrivateKeySigner {
+        TEST_PK.parse().unwrap()
+    }
+
+    /// Minimal remote signer answering `signer_address` and `signer_signHash` with a local key.
+    async fn spawn_remote_stub(signer: PrivateKeySigner) -> String {
+        let route = warp::post()
+            .and(warp::body::json())
+            .then(move |request: Value| {
+                let signer = signer.clone();
+                async move {
+                    let result = match request["method"].as_str() {
+                        Some("signer_address") => json!(signer.address()),
+                        Some("signer_signHash") => {
+                            let hash: B256 =
+                                serde_json::from_value(request["params"][0].clone()).unwrap();
+                            let bytes: [u8; 65] = signer.sign_hash(&hash).await.unwrap().into();
+                            json!(format!("0x{}", alloy::hex::encode(bytes)))
+                        }
+                        _ => Value::Null,
+                    };
+                    warp::reply::json(
+                        &json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }),
+                    )
+                }
+            });
+        let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn test_remote_backend() {
+        let signer = test_signer();
+        let url = spawn_remote_stub(signer.clone()).await;
+
+        let backend = RemoteBackend::connect(&url, None).await.unwrap();
+        assert_eq!(backend.address(), signer.address());
+
+        let hash = keccak256("permit");
+        let signature = backend.sign_hash(&hash).await.unwrap();
+        assert_eq!(
+            signature.recover_address_from_prehash(&hash).unwrap(),
+            signer.address()
+        );
+
+        assert!(RemoteBackend::connect(&url, Some(Address::ZERO))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_keystore_backend() {
+        let dir = std::env::temp_dir().join(format!("floppy-keystore-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (signer, _) = PrivateKeySigner::encrypt_keystore(
+            &dir,
+            &mut rand::thread_rng(),
+            test_signer().to_bytes(),
+            "password",
+            Some("signer.json"),
+        )
+        .unwrap();
+        std::env::set_var("TEST_KEYSTORE_PASSWORD", "password");
+
+        let backend = LocalKeyBackend::from_keystore(
+            dir.join("signer.json").to_str().unwrap(),
+            "TEST_KEYSTORE_PASSWORD",
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(backend.address(), signer.address());
+    }
+
+    #[tokio::test]
+    async fn test_activate_rotates_signer() {
+        let old = Arc::new(LocalKeyBackend::new(test_signer()));
+        let new = Arc::new(LocalKeyBackend::new(PrivateKeySigner::random()));
+        let new_address = new.address();
+        let permit_signer = PermitSigner::new(vec![old.clone(), new]).unwrap();
+        assert_eq!(permit_signer.address(), old.address());
+
+        assert!(!permit_signer.activate(Address::ZERO));
+        assert_eq!(permit_signer.address(), old.address());
+
+        assert!(permit_signer.activate(new_address));
+        let hash = b256!("0000000000000000000000000000000000000000000000000000000000000001");
+        let signature = permit_signer.active().sign_hash(&hash).await.unwrap();
+        assert_eq!(
+            signature.recover_address_from_prehash(&hash).unwrap(),
+            new_address
+        );
+    }
+}
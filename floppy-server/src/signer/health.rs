@@ -0,0 +1,300 @@
+//! Background check that permits are signed with the key the contracts accept.
+//!
+//! `FloppyGamble` exposes `getSigner()`. `FloppyVault` has no getter, so its signer is the one set
+//! by the latest `SignerUpdated` log, found by scanning the vault logs from its deployment block.
+//! `initialize` makes the first admin the signer without that event, so until `setSigner` is
+//! called the signer is the account of the first admin `RoleGranted` log.
+
+use std::sync::{Arc, RwLock};
+
+use alloy::{
+    primitives::{Address, LogData, B256},
+    providers::{Provider, ProviderBuilder, RootProvider},
+    rpc::types::Filter,
+    sol_types::SolEvent,
+    transports::http::Http,
+};
+use alloy_transport_http::{reqwest::Url, Client};
+use eyre::Result;
+use serde::Serialize;
+use tokio::{
+    sync::Mutex,
+    time::{interval, Duration},
+};
+
+use super::{backend::IPermitSigner, PermitSigner};
+use crate::{config::Config, event_listener::FloppyVault, supervisor::Worker};
+
+/// Delay between two signer checks.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Number of blocks requested per `eth_getLogs` call while looking for vault signer updates.
+const LOG_SCAN_RANGE: u64 = 5000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignerStatus {
+    /// The contract signer has not been read yet.
+    Unknown,
+    Ok,
+    /// The contract expects another key, permits would revert with `InvalidSignature`.
+    Mismatch,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContractSignerHealth {
+    pub status: SignerStatus,
+    pub on_chain: Option<Address>,
+    pub checked_at: Option<i64>,
+    pub error: Option<String>,
+}
+
+impl Default for ContractSignerHealth {
+    fn default() -> Self {
+        Self {
+            status: SignerStatus::Unknown,
+            on_chain: None,
+            checked_at: None,
+            error: None,
+        }
+    }
+}
+
+impl ContractSignerHealth {
+    /// Compares the signer read from the contract to ours. A failed read keeps the last known
+    /// status so a flaky RPC does not stop permits that were valid a moment ago.
+    fn update(&mut self, signer: Address, on_chain: Result<Option<Address>>) {
+        match on_chain {
+            Ok(on_chain) => {
+                self.status = match on_chain {
+                    Some(on_chain) if on_chain == signer => SignerStatus::Ok,
+                    Some(_) => SignerStatus::Mismatch,
+                    None => SignerStatus::Unknown,
+                };
+                self.on_chain = on_chain;
+                self.checked_at = Some(chrono::Utc::now().timestamp());
+                self.error = None;
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SignerHealthReport {
+    pub signer: Address,
+    pub gamble: ContractSignerHealth,
+    pub vault: ContractSignerHealth,
+}
+
+impl SignerHealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.gamble.status != SignerStatus::Mismatch && self.vault.status != SignerStatus::Mismatch
+    }
+}
+
+/// Latest vault signer seen and the next block to scan.
+struct VaultSignerScan {
+    next_block: u64,
+    signer: Option<Address>,
+}
+
+pub struct SignerHealth {
+    provider: RootProvider<Http<Client>>,
+    permit_signer: Arc<PermitSigner>,
+    gamble_address: Address,
+    vault_address: Address,
+    vault_scan: Mutex<VaultSignerScan>,
+    report: RwLock<SignerHealthReport>,
+}
+
+impl SignerHealth {
+    pub fn new(config: &Config, permit_signer: Arc<PermitSigner>) -> Result<Self> {
+        let provider = ProviderBuilder::new().on_http(Url::parse(&config.rpc_url)?);
+        let report = SignerHealthReport {
+            signer: permit_signer.address(),
+            gamble: ContractSignerHealth::default(),
+            vault: ContractSignerHealth::default(),
+        };
+        Ok(Self {
+            provider,
+            permit_signer,
+            gamble_address: config.gamble.address,
+            vault_address: config.vault.address,
+            vault_scan: Mutex::new(VaultSignerScan {
+                next_block: config.vault.deployment_block,
+                signer: None,
+            }),
+            report: RwLock::new(report),
+        })
+    }
+
+    pub fn report(&self) -> SignerHealthReport {
+        self.report.read().unwrap().clone()
+    }
+
+    /// Whether gamble permits (bet resolution and cancellation) would be accepted.
+    pub fn can_sign_gamble(&self) -> bool {
+        self.report.read().unwrap().gamble.status != SignerStatus::Mismatch
+    }
+
+    /// Whether vault reward-withdrawal permits would be accepted. Unlike gamble permits they are
+    /// refused until the vault signer is known, as it is only learned from logs.
+    pub fn can_sign_vault(&self) -> bool {
+        self.report.read().unwrap().vault.status == SignerStatus::Ok
+    }
+
+    pub async fn run(&self, mut worker: Worker) -> Result<()> {
+        let mut interval = interval(CHECK_INTERVAL);
+        while worker.tick(&mut interval).await {
+            self.check().await;
+        }
+        Ok(())
+    }
+
+    /// Reads both contract signers, follows a gamble rotation if we hold the new key, and
+    /// records the result.
+    pub async fn check(&self) {
+        let gamble_signer = IPermitSigner::new(self.gamble_address, &self.provider)
+            .getSigner()
+            .call()
+            .await
+            .map(|result| Some(result._0))
+            .map_err(Into::into);
+        if let Ok(Some(on_chain)) = gamble_signer {
+            self.permit_signer.follow(on_chain);
+        }
+        let vault_signer = self.vault_signer().await;
+
+        let mut report = self.report.write().unwrap();
+        let was_healthy = report.is_healthy();
+        report.signer = self.permit_signer.address();
+        let signer = report.signer;
+        report.gamble.update(signer, gamble_signer);
+        report.vault.update(signer, vault_signer);
+
+        if !report.is_healthy() {
+            eprintln!(
+                "Permit signer {} does not match the contracts (gamble: {:?}, vault: {:?}), refusing to issue permits",
+                signer, report.gamble.on_chain, report.vault.on_chain
+            );
+        } else if !was_healthy {
+            println!("Permit signer {} matches the contracts again", signer);
+        }
+        for (name, health) in [("gamble", &report.gamble), ("vault", &report.vault)] {
+            if let Some(error) = &health.error {
+                eprintln!("Cannot read the {} signer: {}", name, error);
+            }
+        }
+    }
+
+    /// Scans vault logs since the last check for signer changes and returns the latest signer.
+    async fn vault_signer(&self) -> Result<Option<Address>> {
+        let mut scan = self.vault_scan.lock().await;
+        let head = self.provider.get_block_number().await?;
+        while scan.next_block <= head {
+            let to_block = (scan.next_block + LOG_SCAN_RANGE - 1).min(head);
+            let filter = Filter::new()
+                .address(self.vault_address)
+                .event_signature(vec![
+                    FloppyVault::SignerUpdated::SIGNATURE_HASH,
+                    FloppyVault::RoleGranted::SIGNATURE_HASH,
+                ])
+                .from_block(scan.next_block)
+                .to_block(to_block);
+            let logs = self.provider.get_logs(&filter).await?;
+            scan.signer = follow_vault_signer(scan.signer, logs.iter().map(|log| log.data()))?;
+            scan.next_block = to_block + 1;
+        }
+        Ok(scan.signer)
+    }
+}
+
+/// Vault signer after applying `logs`, in chain order, to `signer`. The first admin role grant,
+/// from `initialize`, sets the initial signer and later grants are ignored.
+fn follow_vault_signer<'a>(
+    mut signer: Option<Address>,
+    logs: impl IntoIterator<Item = &'a LogData>,
+) -> Result<Option<Address>> {
+    for log in logs {
+        match log.topics().first() {
+            Some(&FloppyVault::SignerUpdated::SIGNATURE_HASH) => {
+                signer = Some(FloppyVault::SignerUpdated::decode_log_data(log, true)?.asset);
+            }
+            Some(&FloppyVault::RoleGranted::SIGNATURE_HASH) if signer.is_none() => {
+                let grant = FloppyVault::RoleGranted::decode_log_data(log, true)?;
+                // DEFAULT_ADMIN_ROLE
+                if grant.role == B256::ZERO {
+                    signer = Some(grant.account);
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(signer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::address;
+    use eyre::eyre;
+
+    #[test]
+    fn test_contract_signer_health_update() {
+        let signer = address!("f39Fd6e51aad88F6F4ce6aB8827279cffFb92266");
+        let other = address!("193542e0C9746e8a428b2a4430545AFdb87d95E8");
+        let mut health = ContractSignerHealth::default();
+
+        health.update(signer, Ok(Some(signer)));
+        assert_eq!(health.status, SignerStatus::Ok);
+
+        // A failed read keeps the last known status
+        health.update(signer, Err(eyre!("rpc down")));
+        assert_eq!(health.status, SignerStatus::Ok);
+        assert!(health.error.is_some());
+
+        health.update(signer, Ok(Some(other)));
+        assert_eq!(health.status, SignerStatus::Mismatch);
+        assert_eq!(health.on_chain, Some(other));
+        assert!(health.error.is_none());
+
+        health.update(signer, Ok(None));
+        assert_eq!(health.status, SignerStatus::Unknown);
+    }
+
+    #[test]
+    fn test_follow_vault_signer() {
+        let admin = address!("f39Fd6e51aad88F6F4ce6aB8827279cffFb92266");
+        let other = address!("193542e0C9746e8a428b2a4430545AFdb87d95E8");
+        let grant = |role: B256, account: Address| {
+            FloppyVault::RoleGranted {
+                role,
+                account,
+                sender: admin,
+            }
+            .encode_log_data()
+        };
+        let initialized = [
+            grant(B256::with_last_byte(1), other),
+            grant(B256::ZERO, admin),
+        ];
+
+        // `setSigner` was never called
+        assert_eq!(
+            follow_vault_signer(None, &initialized).unwrap(),
+            Some(admin)
+        );
+        // Admins granted later do not become the signer
+        assert_eq!(
+            follow_vault_signer(Some(admin), &[grant(B256::ZERO, other)]).unwrap(),
+            Some(admin)
+        );
+
+        let updated = FloppyVault::SignerUpdated { asset: other }.encode_log_data();
+        assert_eq!(
+            follow_vault_signer(None, initialized.iter().chain([&updated])).unwrap(),
+            Some(other)
+        );
+        assert_eq!(follow_vault_signer(None, []).unwrap(), None);
+    }
+}
@@ -1,12 +1,71 @@
+use crate::{
+    auth::Authenticator,
+    bets_syncer::BetsSyncer,
+    config::Config,
+    signer::{PermitSigner, SignerHealth},
+    supervisor::Supervisor,
+    tier_config::TierConfigCache,
+};
 use sqlx::PgPool;
+use std::sync::Arc;
 
 pub struct AppState {
     pub db_pool: PgPool,
-    pub rpc_url: String,
+    pub config: Config,
+    pub signer: Arc<PermitSigner>,
+    pub signer_health: Arc<SignerHealth>,
+    pub tier_config: Arc<TierConfigCache>,
+    pub auth: Arc<Authenticator>,
+    pub bets_syncer: Arc<BetsSyncer>,
+    pub workers: Arc<Supervisor>,
 }
 
 impl AppState {
-    pub fn new(db_pool: PgPool, rpc_url: String) -> Self {
-        Self { db_pool, rpc_url }
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        db_pool: PgPool,
+        config: Config,
+        signer: Arc<PermitSigner>,
+        signer_health: Arc<SignerHealth>,
+        tier_config: Arc<TierConfigCache>,
+        auth: Arc<Authenticator>,
+        bets_syncer: Arc<BetsSyncer>,
+        workers: Arc<Supervisor>,
+    ) -> Self {
+        Self {
+            db_pool,
+            config,
+            signer,
+            signer_health,
+            tier_config,
+            auth,
+            bets_syncer,
+            workers,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{config::tests::test_config, signer::tests::test_signer};
+
+    /// State of a server whose workers never ran, so the chain is only read if a handler does.
+    pub(crate) fn test_state(db_pool: PgPool) -> AppState {
+        let config = test_config();
+        let signer = Arc::new(test_signer());
+        AppState::new(
+            db_pool.clone(),
+            config.clone(),
+            signer.clone(),
+            Arc::new(SignerHealth::new(&config, signer).unwrap()),
+            Arc::new(TierConfigCache::new(&config).unwrap()),
+            Arc::new(Authenticator::with_secret(
+                &config,
+                b"0123456789abcdef0123456789abcdef",
+            )),
+            Arc::new(BetsSyncer::new(&config, db_pool).unwrap()),
+            Arc::new(Supervisor::default()),
+        )
     }
 }
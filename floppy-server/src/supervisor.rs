@@ -0,0 +1,276 @@
+//! Supervision of the background workers.
+//!
+//! Each worker runs in its own task and is restarted with exponential backoff when it fails,
+//! returns or panics. Workers wait for their next iteration through [`Worker::tick`], which also
+//! records their liveness and returns false once shutdown is requested, so an iteration in
+//! flight always runs to completion.
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use eyre::Result;
+use serde::Serialize;
+use tokio::{
+    sync::watch,
+    task::{self, JoinHandle},
+    time::{sleep, timeout, Duration, Instant, Interval},
+};
+
+/// Delay before the first restart of a failed worker.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Longest delay between two restarts.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A worker that ran this long before failing restarts with the initial backoff again.
+const STABLE_RUN: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkerStatus {
+    Running,
+    /// Failed and waiting for its backoff before restarting.
+    Restarting,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkerHealth {
+    pub status: WorkerStatus,
+    /// Running and started an iteration recently enough.
+    pub alive: bool,
+    pub started_at: i64,
+    pub last_beat_at: i64,
+    /// Seconds without an iteration after which the worker is considered stuck.
+    pub stale_after: u64,
+    pub restarts: u64,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<i64>,
+}
+
+impl WorkerHealth {
+    fn new(stale_after: Duration) -> Self {
+        let now = chrono::Utc::now().timestamp();
+        Self {
+            status: WorkerStatus::Running,
+            alive: true,
+            started_at: now,
+            last_beat_at: now,
+            stale_after: stale_after.as_secs(),
+            restarts: 0,
+            last_error: None,
+            last_error_at: None,
+        }
+    }
+
+    fn refresh(&mut self, now: i64) {
+        self.alive = self.status == WorkerStatus::Running
+            && now - self.last_beat_at <= self.stale_after as i64;
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SupervisorReport {
+    pub shutting_down: bool,
+    pub workers: BTreeMap<&'static str, WorkerHealth>,
+}
+
+impl SupervisorReport {
+    pub fn is_healthy(&self) -> bool {
+        self.workers.values().all(|worker| worker.alive)
+    }
+}
+
+/// Handle a worker run receives to pace its iterations and notice shutdown.
+#[derive(Clone)]
+pub struct Worker {
+    shutdown: watch::Receiver<bool>,
+    health: Arc<RwLock<WorkerHealth>>,
+}
+
+impl Worker {
+    /// Waits for the next tick of `interval` and records it as a sign of life. Returns false,
+    /// without waiting for the tick, once shutdown is requested.
+    pub async fn tick(&mut self, interval: &mut Interval) -> bool {
+        let ticked = tokio::select! {
+            biased;
+            _ = self.shutdown.wait_for(|stopping| *stopping) => false,
+            _ = interval.tick() => true,
+        };
+        if ticked {
+            self.beat();
+        }
+        ticked
+    }
+
+    /// Records progress within a long iteration.
+    pub fn beat(&self) {
+        self.health.write().unwrap().last_beat_at = chrono::Utc::now().timestamp();
+    }
+
+    /// Whether shutdown was requested, for workers stopping between batches of an iteration.
+    pub fn is_stopping(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+}
+
+pub struct Supervisor {
+    shutdown: watch::Sender<bool>,
+    workers: RwLock<BTreeMap<&'static str, Arc<RwLock<WorkerHealth>>>>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self {
+            shutdown: watch::Sender::new(false),
+            workers: RwLock::new(BTreeMap::new()),
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Supervisor {
+    /// Runs `run` as worker `name` until shutdown, restarting it whenever it stops on its own.
+    pub fn spawn<F, Fut>(&self, name: &'static str, stale_after: Duration, run: F)
+    where
+        F: Fn(Worker) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let health = Arc::new(RwLock::new(WorkerHealth::new(stale_after)));
+        self.workers.write().unwrap().insert(name, health.clone());
+        let worker = Worker {
+            shutdown: self.shutdown.subscribe(),
+            health,
+        };
+
+        let handle = task::spawn(async move {
+            let mut backoff = INITIAL_BACKOFF;
+            loop {
+                let started = Instant::now();
+                // Run in its own task so a panic is reported like an error
+                let result = task::spawn(run(worker.clone())).await;
+                if worker.is_stopping() {
+                    worker.health.write().unwrap().status = WorkerStatus::Stopped;
+                    return;
+                }
+
+                let error = match result {
+                    Ok(Ok(())) => "exited".to_string(),
+                    Ok(Err(e)) => e.to_string(),
+                    Err(e) => e.to_string(),
+                };
+                if started.elapsed() >= STABLE_RUN {
+                    backoff = INITIAL_BACKOFF;
+                }
+                eprintln!(
+                    "Worker {} stopped: {}, restarting in {:?}",
+                    name, error, backoff
+                );
+                {
+                    let mut health = worker.health.write().unwrap();
+                    health.status = WorkerStatus::Restarting;
+                    health.last_error = Some(error);
+                    health.last_error_at = Some(chrono::Utc::now().timestamp());
+                }
+
+                let mut shutdown = worker.shutdown.clone();
+                tokio::select! {
+                    _ = sleep(backoff) => (),
+                    _ = shutdown.wait_for(|stopping| *stopping) => {
+                        worker.health.write().unwrap().status = WorkerStatus::Stopped;
+                        return;
+                    }
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+
+                let now = chrono::Utc::now().timestamp();
+                let mut health = worker.health.write().unwrap();
+                health.status = WorkerStatus::Running;
+                health.started_at = now;
+                health.last_beat_at = now;
+                health.restarts += 1;
+            }
+        });
+        self.tasks.lock().unwrap().push(handle);
+    }
+
+    pub fn report(&self) -> SupervisorReport {
+        let now = chrono::Utc::now().timestamp();
+        let workers = self
+            .workers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, health)| {
+                let mut health = health.read().unwrap().clone();
+                health.refresh(now);
+                (*name, health)
+            })
+            .collect();
+        SupervisorReport {
+            shutting_down: *self.shutdown.borrow(),
+            workers,
+        }
+    }
+
+    /// Asks every worker to stop after its current iteration.
+    pub fn request_shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Requests shutdown and waits up to `grace` for the workers to stop.
+    pub async fn shutdown(&self, grace: Duration) {
+        self.request_shutdown();
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        let stopped = timeout(grace, async {
+            for task in tasks {
+                let _ = task.await;
+            }
+        })
+        .await;
+        if stopped.is_err() {
+            eprintln!("Workers did not stop within {:?}", grace);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn test_restart_and_shutdown() {
+        let supervisor = Supervisor::default();
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+        supervisor.spawn("flaky", Duration::from_secs(10), move |mut worker| {
+            let counter = counter.clone();
+            async move {
+                if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                    eyre::bail!("rpc unavailable");
+                }
+                let mut interval = tokio::time::interval(Duration::from_secs(1));
+                while worker.tick(&mut interval).await {}
+                Ok(())
+            }
+        });
+
+        // Two failures, restarted after 1s then 2s
+        sleep(Duration::from_millis(3500)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        let report = supervisor.report();
+        let worker = &report.workers["flaky"];
+        assert_eq!(worker.status, WorkerStatus::Running);
+        assert_eq!(worker.restarts, 2);
+        assert_eq!(worker.last_error.as_deref(), Some("rpc unavailable"));
+
+        supervisor.shutdown(Duration::from_secs(5)).await;
+        let report = supervisor.report();
+        assert_eq!(report.workers["flaky"].status, WorkerStatus::Stopped);
+        assert!(!report.is_healthy());
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+}
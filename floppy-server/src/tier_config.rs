@@ -0,0 +1,167 @@
+//! Cached copy of the `FloppyGamble` tier configuration, used to preview bet outcomes off-chain.
+//!
+//! The cache is filled on first use and refreshed by the event listener whenever the contract
+//! emits `PointsRangesUpdated`, `RewardPercentagesUpdated` or a bet amount bound update.
+
+use std::sync::RwLock;
+
+use alloy::{primitives::U256, providers::ProviderBuilder};
+use alloy_transport_http::reqwest::Url;
+use eyre::Result;
+use serde::Serialize;
+
+use crate::{
+    config::Config,
+    event_listener::{FloppyGamble, GambleContract},
+};
+
+/// Denominator of reward percentages, `FloppyGamble.MAX_PERCENTAGE`.
+pub const MAX_PERCENTAGE: u64 = 100_000;
+/// Number of `BetTier` values, including `Unknown`.
+const TIER_COUNT: u8 = 5;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TierRule {
+    pub min_points: U256,
+    pub max_points: U256,
+    /// Out of [`MAX_PERCENTAGE`].
+    pub reward_percentage: U256,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TierConfig {
+    /// Indexed by `BetTier` as a number, `Unknown` being 0.
+    pub tiers: Vec<TierRule>,
+    pub min_bet_amount: U256,
+    pub max_bet_amount: U256,
+    pub refreshed_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BetPreview {
+    pub points: U256,
+    pub min_points: U256,
+    pub max_points: U256,
+    pub win: bool,
+    pub reward: U256,
+}
+
+impl TierConfig {
+    /// Computes the outcome of `resolveBet` for a bet of `bet_amount` in `tier` scoring `points`.
+    pub fn preview(&self, tier: u8, bet_amount: U256, points: U256) -> Option<BetPreview> {
+        let rule = self.tiers.get(tier as usize)?;
+        let win = points >= rule.min_points;
+        let reward = if win {
+            bet_amount.saturating_mul(rule.reward_percentage) / U256::from(MAX_PERCENTAGE)
+        } else {
+            U256::ZERO
+        };
+        Some(BetPreview {
+            points,
+            min_points: rule.min_points,
+            max_points: rule.max_points,
+            win,
+            reward,
+        })
+    }
+}
+
+pub struct TierConfigCache {
+    gamble_contract: GambleContract,
+    config: RwLock<Option<TierConfig>>,
+}
+
+impl TierConfigCache {
+    pub fn new(config: &Config) -> Result<Self> {
+        let provider = ProviderBuilder::new().on_http(Url::parse(&config.rpc_url)?);
+        Ok(Self {
+            gamble_contract: FloppyGamble::new(config.gamble.address, provider),
+            config: RwLock::new(None),
+        })
+    }
+
+    /// The cached configuration, read from the contract if it was never loaded.
+    pub async fn get(&self) -> Result<TierConfig> {
+        if let Some(config) = self.config.read().unwrap().clone() {
+            return Ok(config);
+        }
+        self.refresh().await
+    }
+
+    /// Reads the tier configuration from the contract and replaces the cached one.
+    pub async fn refresh(&self) -> Result<TierConfig> {
+        let mut tiers = Vec::with_capacity(TIER_COUNT as usize);
+        for tier in 0..TIER_COUNT {
+            let range = self
+                .gamble_contract
+                .getPointsRangeForTier(tier)
+                .call()
+                .await?;
+            // Percentages are not exposed, but getReward(tier, MAX_PERCENTAGE) returns them as is
+            let reward_percentage = self
+                .gamble_contract
+                .getReward(tier, U256::from(MAX_PERCENTAGE))
+                .call()
+                .await?
+                ._0;
+            tiers.push(TierRule {
+                min_points: range._0,
+                max_points: range._1,
+                reward_percentage,
+            });
+        }
+        let config = TierConfig {
+            tiers,
+            min_bet_amount: self.gamble_contract.getMinBetAmount().call().await?._0,
+            max_bet_amount: self.gamble_contract.getMaxBetAmount().call().await?._0,
+            refreshed_at: chrono::Utc::now().timestamp(),
+        };
+        *self.config.write().unwrap() = Some(config.clone());
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tier_config() -> TierConfig {
+        let rule = |min_points: u64, max_points: u64, reward_percentage: u64| TierRule {
+            min_points: U256::from(min_points),
+            max_points: U256::from(max_points),
+            reward_percentage: U256::from(reward_percentage),
+        };
+        TierConfig {
+            tiers: vec![
+                rule(0, 0, 0),
+                rule(50, 100, 150_000),
+                rule(101, 200, 200_000),
+                rule(201, 400, 300_000),
+                rule(401, u64::MAX, 500_000),
+            ],
+            min_bet_amount: U256::from(1),
+            max_bet_amount: U256::from(1_000_000),
+            refreshed_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_preview() {
+        let config = tier_config();
+        let amount = U256::from(10_000_000_000_000_000_001u128);
+
+        let won = config.preview(2, amount, U256::from(101)).unwrap();
+        assert!(won.win);
+        assert_eq!(won.reward, U256::from(20_000_000_000_000_000_002u128));
+
+        let lost = config.preview(2, amount, U256::from(100)).unwrap();
+        assert!(!lost.win);
+        assert_eq!(lost.reward, U256::ZERO);
+
+        // Rounds down like the contract
+        let small = config.preview(1, U256::from(3), U256::from(50)).unwrap();
+        assert_eq!(small.reward, U256::from(4));
+
+        assert!(config.preview(5, amount, U256::ZERO).is_none());
+    }
+}